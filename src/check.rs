@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+
+use chrono::format::{Item, StrftimeItems};
+use serde_json::{json, Value};
+
+use crate::condition::Condition;
+use crate::custom_dialog::{CustomDialog, Field, Page};
+
+/// Problem found in a layout, with the path of the entry which has it, like `body[2].row.body[0]`.
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+    /// Warnings don't stop the dialog from being shown
+    pub warning: bool,
+}
+
+impl Problem {
+    fn error(path: &str, message: impl ToString) -> Self {
+        Problem {
+            path: path.to_string(),
+            message: message.to_string(),
+            warning: false,
+        }
+    }
+
+    fn warning(path: &str, message: impl ToString) -> Self {
+        Problem {
+            path: path.to_string(),
+            message: message.to_string(),
+            warning: true,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = if self.warning { "warning" } else { "error" };
+        match self.path.as_str() {
+            "" => write!(f, "{}: {}", level, self.message),
+            path => write!(f, "{}: {}: {}", level, path, self.message),
+        }
+    }
+}
+
+/// Checks the structure and the meaning of a layout which couldn't be deserialized yet, so every
+/// problem is reported and not only the first one.
+pub fn check_layout(layout: &Value) -> Vec<Problem> {
+    let (mut problems, checkable) = check_structure(layout);
+    if let Ok(dialog) = serde_json::from_value::<CustomDialog>(checkable) {
+        problems.extend(check_dialog(&dialog));
+        // A layout checked on its own should only have readable images
+        problems.extend(check_images(&dialog).into_iter().map(|mut problem| {
+            problem.warning = false;
+            problem
+        }));
+    }
+    problems
+}
+
+/// Checks the layout entry by entry, like when the whole layout is deserialized at once, but
+/// without stopping at the first problem. Returns the problems and the layout where the wrong
+/// entries are replaced by empty ones, so the meaning of the rest can still be checked.
+fn check_structure(layout: &Value) -> (Vec<Problem>, Value) {
+    let mut problems = Vec::new();
+    let Some(root) = layout.as_object() else {
+        let problem = Problem::error("", "the layout must be an object");
+        return (vec![problem], Value::Object(Default::default()));
+    };
+    // The fields are checked on their own, the rest of the dialog without them
+    let mut dialog = root.clone();
+    let body = dialog.remove("body");
+    let pages = dialog.remove("pages");
+    if let Err(e) = serde_json::from_value::<CustomDialog>(Value::Object(dialog.clone())) {
+        problems.push(Problem::error("", e));
+        dialog.clear();
+    }
+    if let Some(mut body) = body {
+        check_fields(&mut body, "body", &mut problems);
+        dialog.insert("body".to_string(), body);
+    }
+    match pages {
+        None => {}
+        Some(Value::Array(mut pages)) => {
+            for (index, page) in pages.iter_mut().enumerate() {
+                let path = format!("pages[{}]", index);
+                let mut body = match page.get_mut("body") {
+                    Some(body) => body.take(),
+                    None => {
+                        problems.push(Problem::error(&path, "missing field `body`"));
+                        Value::Array(Vec::new())
+                    }
+                };
+                check_fields(&mut body, &format!("{}.body", path), &mut problems);
+                if let Some(object) = page.as_object_mut() {
+                    object.insert("body".to_string(), Value::Array(Vec::new()));
+                }
+                if let Err(e) = serde_json::from_value::<Page>(page.clone()) {
+                    problems.push(Problem::error(&path, e));
+                    *page = json!({});
+                }
+                page["body"] = body;
+            }
+            dialog.insert("pages".to_string(), Value::Array(pages));
+        }
+        Some(_) => problems.push(Problem::error("pages", "expected a list of pages")),
+    }
+    (problems, Value::Object(dialog))
+}
+
+/// Checks every field on its own. The ones which can't be deserialized are replaced by an empty
+/// label.
+fn check_fields(fields: &mut Value, path: &str, problems: &mut Vec<Problem>) {
+    let Value::Array(list) = fields else {
+        problems.push(Problem::error(path, "expected a list of fields"));
+        *fields = Value::Array(Vec::new());
+        return;
+    };
+    for (index, field) in list.iter_mut().enumerate() {
+        let path = format!("{}[{}]", path, index);
+        // The children of the containers are checked before the container, which is checked
+        // without them
+        let mut bare = field.clone();
+        if let Some((kind, properties)) = container(field) {
+            if let Some(body) = properties.get_mut("body") {
+                check_fields(body, &format!("{}.{}.body", path, kind), problems);
+            }
+            if let Some(Value::Array(tabs)) = properties.get_mut("tabs") {
+                for (tab_index, tab) in tabs.iter_mut().enumerate() {
+                    if let Some(body) = tab.get_mut("body") {
+                        let tab_path = format!("{}.{}.tabs[{}].body", path, kind, tab_index);
+                        check_fields(body, &tab_path, problems);
+                    }
+                }
+            }
+        }
+        if let Some((_, properties)) = container(&mut bare) {
+            if let Some(body) = properties.get_mut("body") {
+                *body = Value::Array(Vec::new());
+            }
+            if let Some(Value::Array(tabs)) = properties.get_mut("tabs") {
+                for tab in tabs.iter_mut() {
+                    if let Some(body) = tab.get_mut("body") {
+                        *body = Value::Array(Vec::new());
+                    }
+                }
+            }
+        }
+        if let Err(e) = serde_json::from_value::<Field>(bare) {
+            problems.push(Problem::error(&path, e));
+            *field = json!({ "label": { "text": "" } });
+        }
+    }
+}
+
+/// Kind and properties of a field, like `row` and its `body`
+fn container(field: &mut Value) -> Option<(&String, &mut Value)> {
+    field
+        .as_object_mut()
+        .filter(|field| field.len() == 1)
+        .and_then(|field| field.iter_mut().next())
+}
+
+/// Checks the meaning of a layout which could be deserialized: the ids, the initial values and
+/// the conditions. The images are checked apart, by `check_images`.
+pub fn check_dialog(dialog: &CustomDialog) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut ids: HashMap<&str, String> = HashMap::new();
+    visit_dialog(dialog, &mut |field, path| {
+        if let Some(id) = field.id() {
+            match ids.get(id) {
+                Some(first) => problems.push(Problem::error(
+                    path,
+                    format!("duplicate id '{}', already used in {}", id, first),
+                )),
+                None => {
+                    ids.insert(id, path.to_string());
+                }
+            }
+        }
+        if let Some(error) = check_value(field) {
+            problems.push(Problem::error(path, error));
+        }
+    });
+
+    // The conditions are checked once every id is known
+    visit_dialog(dialog, &mut |field, path| {
+        let conditions = field.conditions();
+        for (name, condition) in [
+            ("visible_if", &conditions.visible_if),
+            ("enabled_if", &conditions.enabled_if),
+        ] {
+            check_condition(
+                condition,
+                &format!("{}.{}", path, name),
+                &ids,
+                &mut problems,
+            );
+        }
+    });
+    for (index, page) in dialog.pages.iter().enumerate() {
+        let path = format!("pages[{}].skip_if", index);
+        check_condition(&page.skip_if, &path, &ids, &mut problems);
+    }
+    problems
+}
+
+fn check_value(field: &Field) -> Option<String> {
+    match field {
+        Field::Radio {
+            selected, options, ..
+        }
+        | Field::Combobox {
+            selected, options, ..
+        } if !selected.is_empty() && !options.contains(selected) => Some(format!(
+            "selected '{}' is not one of the options {:?}",
+            selected, options
+        )),
+        Field::List {
+            selected, values, ..
+        } if !selected.is_empty() && !values.contains(selected) => Some(format!(
+            "selected '{}' is not one of the values {:?}",
+            selected, values
+        )),
+        Field::Slider { min, max, .. } if min > max => {
+            Some(format!("min {} is greater than max {}", min, max))
+        }
+        Field::Slider {
+            min, max, value, ..
+        } if value < min || value > max => Some(format!(
+            "value {} is out of the range {}..{}",
+            value, min, max
+        )),
+        Field::Calendar { date_format, .. }
+            if StrftimeItems::new(date_format).any(|item| matches!(item, Item::Error)) =>
+        {
+            Some(format!("invalid date_format '{}'", date_format))
+        }
+        _ => None,
+    }
+}
+
+/// A condition with an unknown id is allowed (its value is empty), but it is probably a typo
+fn check_condition(
+    condition: &Option<Condition>,
+    path: &str,
+    ids: &HashMap<&str, String>,
+    problems: &mut Vec<Problem>,
+) {
+    for id in condition.iter().flat_map(|condition| condition.ids()) {
+        if !ids.contains_key(id) {
+            problems.push(Problem::warning(
+                path,
+                format!("there is no field with id '{}'", id),
+            ));
+        }
+    }
+}
+
+/// Checks the images of the layout. A path which isn't an URI is an error, but a file which can't
+/// be read is only a warning: relative paths depend on the current folder, so a missing image
+/// isn't always a mistake in the layout.
+pub fn check_images(dialog: &CustomDialog) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    visit_dialog(dialog, &mut |field, path| {
+        if let Field::Image { path: uri, .. } = field {
+            if let Some(error) = check_image(uri) {
+                problems.push(if uri.starts_with("file://") {
+                    Problem::warning(path, error)
+                } else {
+                    Problem::error(path, error)
+                });
+            }
+        }
+    });
+    problems
+}
+
+/// Checks that an image URI can be loaded: local files must exist, remote ones aren't checked.
+pub fn check_image(uri: &str) -> Option<String> {
+    match uri.strip_prefix("file://") {
+        Some(path) => File::open(path)
+            .err()
+            .map(|e| format!("unable to read image '{}': {}", uri, e)),
+        // Remote images can't be checked without downloading them
+        None if uri.contains("://") => None,
+        None => Some(format!(
+            "the image path '{}' must be an URI, like 'file://{}'",
+            uri, uri
+        )),
+    }
+}
+
+/// Calls `visit` with every field of the dialog, containers included, and its path.
+fn visit_dialog<'a>(dialog: &'a CustomDialog, visit: &mut impl FnMut(&'a Field, &str)) {
+    visit_fields(&dialog.body, "body", visit);
+    for (index, page) in dialog.pages.iter().enumerate() {
+        visit_fields(&page.body, &format!("pages[{}].body", index), visit);
+    }
+}
+
+fn visit_fields<'a>(fields: &'a [Field], path: &str, visit: &mut impl FnMut(&'a Field, &str)) {
+    for (index, field) in fields.iter().enumerate() {
+        let path = format!("{}[{}]", path, index);
+        visit(field, &path);
+        match field {
+            Field::Row { body, .. } => visit_fields(body, &format!("{}.row.body", path), visit),
+            Field::Column { body, .. } => {
+                visit_fields(body, &format!("{}.column.body", path), visit)
+            }
+            Field::Group { body, .. } => visit_fields(body, &format!("{}.group.body", path), visit),
+            Field::Collapsing { body, .. } => {
+                visit_fields(body, &format!("{}.collapsing.body", path), visit)
+            }
+            Field::Grid { body, .. } => visit_fields(body, &format!("{}.grid.body", path), visit),
+            Field::Tabs { tabs, .. } => {
+                for (tab_index, tab) in tabs.iter().enumerate() {
+                    let tab_path = format!("{}.tabs.tabs[{}].body", path, tab_index);
+                    visit_fields(&tab.body, &tab_path, visit);
+                }
+            }
+            _ => {}
+        }
+    }
+}
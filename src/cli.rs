@@ -1,4 +1,11 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
+use notify_rust::{Hint, Timeout};
+
+use crate::custom_dialog::{Button, TimeoutAction};
+use crate::layout::{parse_var, LayoutFormat};
+use crate::response::{OutputFormat, ResponseVersion};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -7,6 +14,36 @@ pub struct Cli {
     // Path of the icon
     #[arg(long)]
     pub icon_path: Option<String>,
+    /// Print an event line in stdout every time a field value changes
+    #[arg(long, global = true)]
+    pub events: bool,
+    /// Button of the dialog, repeatable: id:label[:default,escape,cancel,no-validate,shortcut=ctrl+s]
+    #[arg(long = "button", global = true)]
+    pub buttons: Vec<Button>,
+    /// Seconds until the dialog closes by itself
+    #[arg(long, global = true)]
+    pub timeout: Option<u64>,
+    /// What to do when the timeout expires [default: cancel]
+    #[arg(long, value_enum, global = true)]
+    pub timeout_action: Option<TimeoutAction>,
+    /// Show the remaining seconds of the timeout
+    #[arg(long, global = true)]
+    pub countdown: bool,
+    /// Format of the response: 1 prints every value as a string, 2 prints native JSON values
+    #[arg(long, value_enum, global = true, default_value_t = ResponseVersion::V1)]
+    pub response_version: ResponseVersion,
+    /// Format of the final response
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Json)]
+    pub output_format: OutputFormat,
+    /// Response of a previous run (JSON format) whose values are the initial values of the fields
+    #[arg(long, global = true)]
+    pub defaults: Option<PathBuf>,
+    /// Write the response and the events to this file instead of stdout
+    #[arg(long, global = true, conflicts_with = "output_fd")]
+    pub output_file: Option<PathBuf>,
+    /// Write the response and the events to this open file descriptor instead of stdout (unix only)
+    #[arg(long, global = true)]
+    pub output_fd: Option<i32>,
     #[command(subcommand)]
     pub command: Command,
 }
@@ -21,13 +58,57 @@ pub enum Command {
         /// Text of the notification
         #[arg(long)]
         text: String,
+        /// Button of the notification, repeatable: id:label
+        #[arg(long = "action", value_parser = parse_action)]
+        actions: Vec<(String, String)>,
+        /// Wait until an action is clicked or the notification is closed, and print the response
+        #[arg(long)]
+        wait: bool,
+        /// Urgency of the notification
+        #[arg(long, value_enum)]
+        urgency: Option<NotificationUrgency>,
+        /// Milliseconds until the notification expires, `never` or `default`
+        #[arg(long)]
+        expire_timeout: Option<Timeout>,
+        /// Category of the notification, like `email.arrived`
+        #[arg(long)]
+        category: Option<String>,
+        /// Name of the application which sends the notification
+        #[arg(long)]
+        app_name: Option<String>,
+        /// Themeable sound played with the notification, like `message-new-instant`
+        #[arg(long)]
+        sound_name: Option<String>,
+        /// Don't keep the notification in the history of the server
+        #[arg(long)]
+        transient: bool,
+        /// Don't close the notification when an action is clicked
+        #[arg(long)]
+        resident: bool,
+        /// Hint for the notification server, repeatable: key=value
+        #[arg(long = "hint", value_parser = parse_hint)]
+        hints: Vec<Hint>,
+        /// Id of a notification shown before, to update it instead of showing a new one
+        #[arg(long)]
+        replace_id: Option<u32>,
+        /// Show a progress bar updated with the progress commands read from stdin, until it
+        /// reaches 100 or stdin is closed
+        #[arg(long, conflicts_with = "wait")]
+        progress: bool,
+    },
+    /// Close a notification shown before
+    NotificationClose {
+        /// Id of the notification, given in the response of the notification command
+        #[arg(long)]
+        id: u32,
     },
     /// File/Directory selection dialog
     FileDialog {
         /// Flag for directory selection
         #[arg(long)]
         is_directory: bool,
-        /// Flag for directory selection
+        /// Directory where the dialog starts. When it is a file, the dialog starts in its
+        /// directory with the file selected
         #[arg(long, default_value_t = String::from(""))]
         open_directory: String,
         /// Flag for multiple selection
@@ -36,6 +117,15 @@ pub enum Command {
         /// Flag to be a save dialog
         #[arg(long)]
         save: bool,
+        /// Title of the dialog
+        #[arg(long)]
+        title: Option<String>,
+        /// Default name of the file, for save dialogs
+        #[arg(long)]
+        file_name: Option<String>,
+        /// Allowed file types, repeatable: name:extension,extension
+        #[arg(long = "filter", value_parser = parse_filter)]
+        filters: Vec<(String, Vec<String>)>,
     },
     /// Message dialog
     MessageDialog {
@@ -129,10 +219,36 @@ pub enum Command {
     },
     /// Custom dialog
     Custom {
-        /// Path of the custom dialog layout
-        #[arg(long)]
-        layout_path: String,
+        /// Path of the custom dialog layout, or - to read it from stdin
+        #[arg(
+            long,
+            visible_alias = "layout",
+            required_unless_present = "layout_json"
+        )]
+        layout_path: Option<String>,
+        /// Custom dialog layout given inline
+        #[arg(long, conflicts_with = "layout_path")]
+        layout_json: Option<String>,
+        /// Format of the layout. By default, it is given by the extension of the file, or JSON
+        #[arg(long, value_enum)]
+        layout_format: Option<LayoutFormat>,
+        /// Variable replacing the `__key__` placeholders of the layout, repeatable: key=value
+        #[arg(long = "var", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+    },
+    /// Check a custom dialog layout without showing it
+    Validate {
+        /// Path of the custom dialog layout, or - to read it from stdin
+        layout: String,
+        /// Format of the layout. By default, it is given by the extension of the file, or JSON
+        #[arg(long, value_enum)]
+        layout_format: Option<LayoutFormat>,
+        /// Variable replacing the `__key__` placeholders of the layout, repeatable: key=value
+        #[arg(long = "var", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
     },
+    /// Print the JSON schema of the custom dialog layouts
+    Schema,
 }
 
 #[derive(clap::ValueEnum, Clone)]
@@ -142,3 +258,56 @@ pub enum MessageDialogLevel {
     Error,
     Question,
 }
+
+#[derive(clap::ValueEnum, Clone)]
+pub enum NotificationUrgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+fn parse_action(action: &str) -> Result<(String, String), String> {
+    match action.split_once(':') {
+        Some((id, label)) if !id.is_empty() => Ok((id.to_string(), label.to_string())),
+        _ => Err(format!("expected id:label, found '{}'", action)),
+    }
+}
+
+/// File type of the file dialog, like `Images:png,jpg`. The extensions can be written as `*.png`
+/// or `.png` too.
+fn parse_filter(filter: &str) -> Result<(String, Vec<String>), String> {
+    let (name, extensions) = match filter.split_once(':') {
+        Some((name, extensions)) if !name.is_empty() => (name, extensions),
+        _ => return Err(format!("expected name:extensions, found '{}'", filter)),
+    };
+    let extensions: Vec<String> = extensions
+        .split(',')
+        .map(|extension| {
+            let extension = extension.trim();
+            extension
+                .strip_prefix("*.")
+                .or(extension.strip_prefix('.'))
+                .unwrap_or(extension)
+        })
+        .filter(|extension| !extension.is_empty())
+        .map(String::from)
+        .collect();
+    if extensions.is_empty() {
+        return Err(format!("missing extensions in the filter '{}'", filter));
+    }
+    Ok((name.to_string(), extensions))
+}
+
+/// Standard hint of the notification specification, or a custom one (an integer when the value is
+/// a number).
+fn parse_hint(hint: &str) -> Result<Hint, String> {
+    let (key, value) = parse_var(hint)?;
+    match Hint::from_key_val(&key, &value) {
+        Ok(hint) => Ok(hint),
+        Err(error) if error == "unknown name" => Ok(match value.parse::<i32>() {
+            Ok(number) => Hint::CustomInt(key, number),
+            Err(_) => Hint::Custom(key, value),
+        }),
+        Err(error) => Err(format!("invalid value for the hint '{}': {}", key, error)),
+    }
+}
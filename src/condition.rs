@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// Conditions shared by every field to show or enable it depending on other fields.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Conditions {
+    #[serde(default)]
+    pub visible_if: Option<Condition>,
+    #[serde(default)]
+    pub enabled_if: Option<Condition>,
+}
+
+impl Conditions {
+    pub fn is_visible(&self, values: &HashMap<String, String>) -> bool {
+        self.visible_if.as_ref().map_or(true, |c| c.eval(values))
+    }
+
+    pub fn is_enabled(&self, values: &HashMap<String, String>) -> bool {
+        self.enabled_if.as_ref().map_or(true, |c| c.eval(values))
+    }
+}
+
+/// Expression evaluated against the current values of the fields, referenced by their ids.
+///
+/// * `use_proxy` is true when the value is not empty, `false` nor `0`
+/// * `!use_proxy` negates it
+/// * `mode == manual` and `mode != 'manual'` compare the value with a string
+/// * terms can be joined with `&&` and `||` (`&&` binds tighter, no parentheses)
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    any: Vec<Vec<Term>>,
+}
+
+#[derive(Debug)]
+enum Term {
+    Truthy {
+        id: String,
+        negated: bool,
+    },
+    Compare {
+        id: String,
+        value: String,
+        equal: bool,
+    },
+}
+
+impl Condition {
+    pub fn eval(&self, values: &HashMap<String, String>) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|term| term.eval(values)))
+    }
+
+    /// Ids of the fields used in the condition
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.any.iter().flatten().map(|term| match term {
+            Term::Truthy { id, .. } | Term::Compare { id, .. } => id.as_str(),
+        })
+    }
+}
+
+impl Term {
+    fn eval(&self, values: &HashMap<String, String>) -> bool {
+        match self {
+            Term::Truthy { id, negated } => {
+                let value = values.get(id).map(|v| v.as_str()).unwrap_or("");
+                let truthy = !value.is_empty() && value != "false" && value != "0";
+                truthy != *negated
+            }
+            Term::Compare { id, value, equal } => {
+                let current = values.get(id).map(|v| v.as_str()).unwrap_or("");
+                (current == value) == *equal
+            }
+        }
+    }
+
+    fn parse(term: &str) -> Result<Term, String> {
+        let term = term.trim();
+        // The first operator is the comparison, the value may contain another one
+        let operator = ["==", "!="]
+            .into_iter()
+            .filter_map(|operator| term.find(operator).map(|index| (index, operator)))
+            .min();
+        if let Some((index, operator)) = operator {
+            let value = term[index + operator.len()..].trim();
+            let value = value
+                .strip_prefix('\'')
+                .and_then(|v| v.strip_suffix('\''))
+                .or(value.strip_prefix('"').and_then(|v| v.strip_suffix('"')))
+                .unwrap_or(value);
+            return Ok(Term::Compare {
+                id: parse_id(&term[..index])?,
+                value: value.to_string(),
+                equal: operator == "==",
+            });
+        }
+        match term.strip_prefix('!') {
+            Some(id) => Ok(Term::Truthy {
+                id: parse_id(id)?,
+                negated: true,
+            }),
+            None => Ok(Term::Truthy {
+                id: parse_id(term)?,
+                negated: false,
+            }),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<String, String> {
+    let id = id.trim();
+    if id.is_empty() || id.contains(char::is_whitespace) {
+        return Err(format!("invalid field id '{}' in condition", id));
+    }
+    Ok(id.to_string())
+}
+
+impl JsonSchema for Condition {
+    fn schema_name() -> String {
+        "Condition".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let any = split_unquoted(&source, "||")
+            .into_iter()
+            .map(|all| {
+                split_unquoted(all, "&&")
+                    .into_iter()
+                    .map(Term::parse)
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Condition { any })
+    }
+}
+
+/// Splits on `separator` except inside quoted values, `mode == 'a && b'` is a single term
+fn split_unquoted<'a>(source: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in source.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if index >= start && source[index..].starts_with(separator) => {
+                parts.push(&source[start..index]);
+                start = index + separator.len();
+            }
+            None => {}
+        }
+    }
+    parts.push(&source[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(condition: &str, values: &[(&str, &str)]) -> bool {
+        let condition = Condition::try_from(condition.to_string()).unwrap();
+        let values = values
+            .iter()
+            .map(|(id, value)| (id.to_string(), value.to_string()))
+            .collect();
+        condition.eval(&values)
+    }
+
+    #[test]
+    fn truthy_and_negated() {
+        assert!(eval("proxy", &[("proxy", "true")]));
+        assert!(!eval("proxy", &[("proxy", "false")]));
+        assert!(!eval("proxy", &[("proxy", "0")]));
+        assert!(!eval("proxy", &[]));
+        assert!(eval("!proxy", &[("proxy", "")]));
+        assert!(!eval("! proxy", &[("proxy", "yes")]));
+    }
+
+    #[test]
+    fn compare_quoted_values() {
+        let values = [("mode", "manual mode")];
+        assert!(eval("mode == 'manual mode'", &values));
+        assert!(eval("mode == \"manual mode\"", &values));
+        assert!(eval("mode != manual", &values));
+        assert!(!eval("mode != 'manual mode'", &values));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let condition = "a && b || c";
+        assert!(eval(condition, &[("a", "1"), ("b", "1")]));
+        assert!(eval(condition, &[("c", "1")]));
+        assert!(!eval(condition, &[("a", "1")]));
+        assert!(!eval(condition, &[("b", "1")]));
+        assert!(eval("a || b && c", &[("a", "1")]));
+        assert!(!eval("a || b && c", &[("b", "1")]));
+    }
+
+    #[test]
+    fn operators_inside_values() {
+        assert!(eval("op == '!='", &[("op", "!=")]));
+        assert!(eval("op != 'a==b'", &[("op", "a")]));
+        assert!(eval("op == 'a && b'", &[("op", "a && b")]));
+        assert!(eval(
+            "op == \"a || b\" && x",
+            &[("op", "a || b"), ("x", "1")]
+        ));
+    }
+
+    #[test]
+    fn empty_id_is_an_error() {
+        for condition in ["", "a &&", "|| a", "== 'x'", "!", "two words"] {
+            let error = Condition::try_from(condition.to_string()).unwrap_err();
+            assert!(error.starts_with("invalid field id"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn ids() {
+        let condition = Condition::try_from("a && !b || c == 'd'".to_string()).unwrap();
+        assert_eq!(condition.ids().collect::<Vec<_>>(), ["a", "b", "c"]);
+    }
+}
@@ -1,9 +1,21 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
 use chrono::{Datelike, NaiveDate, Utc};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::Deserialize;
+use serde_json::{json, Value};
 
+use crate::check;
+use crate::condition::{Condition, Conditions};
 use crate::gui::{HAlign, HLabelPos, LabelPos};
+use crate::response::ResponseBody;
+use crate::validation::{Checked, Validation};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct CustomDialog {
     #[serde(default)]
     pub title: Option<String>,
@@ -15,22 +27,229 @@ pub struct CustomDialog {
     pub window_size: (f32, f32),
     #[serde(default = "default_pos")]
     pub window_pos: (f32, f32),
+    /// Print an event line every time the value of a field changes
+    #[serde(default)]
+    pub events: bool,
+    #[serde(default)]
     pub body: Vec<Field>,
+    /// Pages of a wizard dialog. When given, `body` is ignored
+    #[serde(default)]
+    pub pages: Vec<Page>,
+    /// Buttons of the dialog. "Ok" and "Cancel" are used when empty
+    #[serde(default)]
+    pub buttons: Vec<Button>,
+    /// Seconds until the dialog closes by itself
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    #[serde(default = "default_timeout_action")]
+    pub timeout_action: TimeoutAction,
+    /// Show the remaining seconds next to the buttons
+    #[serde(default)]
+    pub countdown: bool,
 }
 
-#[derive(Debug, Deserialize)]
+impl CustomDialog {
+    /// Sets the values of the fields by their id. Returns why each value couldn't be set
+    pub fn set_values(&mut self, values: Vec<(String, Value)>) -> Vec<String> {
+        let CustomDialog { body, pages, .. } = self;
+        let mut errors = Vec::new();
+        for (id, value) in values {
+            let field = find_field_mut(body, &id).or_else(|| {
+                pages
+                    .iter_mut()
+                    .find_map(|page| find_field_mut(&mut page.body, &id))
+            });
+            let result = match field {
+                Some(field) => field.set_value(&value),
+                None => Err("no field with this id".to_string()),
+            };
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", id, e));
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutAction {
+    /// Return a timeout response
+    Cancel,
+    /// Return the current values of the fields, without validating them
+    Accept,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Page {
+    #[serde(default)]
+    pub title: Option<String>,
+    pub body: Vec<Field>,
+    /// The page is not shown (nor returned) when this condition is true
+    #[serde(default)]
+    pub skip_if: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct Button {
+    pub id: String,
+    pub label: String,
+    #[serde(default = "default_button_result")]
+    pub result: ButtonResult,
+    /// Check the fields before closing the dialog with an `ok` result
+    #[serde(default = "default_true")]
+    pub validate: bool,
+    /// Pressed with Enter
+    #[serde(default)]
+    pub default: bool,
+    /// Pressed with Escape or closing the window
+    #[serde(default)]
+    pub escape: bool,
+    /// Key combination to press the button, like `ctrl+s`
+    #[serde(default)]
+    pub shortcut: Option<Shortcut>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonResult {
+    /// Return the values of the fields
+    Ok,
+    /// Return a cancel response
+    Cancel,
+}
+
+impl Button {
+    pub fn ok(label: &str) -> Self {
+        Button {
+            id: "ok".to_string(),
+            label: label.to_string(),
+            result: ButtonResult::Ok,
+            validate: true,
+            default: true,
+            escape: false,
+            shortcut: None,
+        }
+    }
+
+    pub fn cancel() -> Self {
+        Button {
+            id: "cancel".to_string(),
+            label: "Cancel".to_string(),
+            result: ButtonResult::Cancel,
+            validate: false,
+            default: false,
+            escape: true,
+            shortcut: None,
+        }
+    }
+}
+
+/// Parses buttons from the command line: `id:label[:option,option...]`, where the options
+/// are `default`, `escape`, `cancel`, `no-validate` and `shortcut=<keys>`.
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let id = parts.next().unwrap_or_default();
+        let label = parts.next().unwrap_or(id);
+        if id.is_empty() {
+            return Err(format!("missing button id in '{}'", s));
+        }
+        let mut button = Button {
+            id: id.to_string(),
+            label: label.to_string(),
+            result: ButtonResult::Ok,
+            validate: true,
+            default: false,
+            escape: false,
+            shortcut: None,
+        };
+        for option in parts.next().unwrap_or_default().split(',') {
+            match option.trim() {
+                "" => {}
+                "default" => button.default = true,
+                "escape" => button.escape = true,
+                "cancel" => button.result = ButtonResult::Cancel,
+                "no-validate" => button.validate = false,
+                option => match option.strip_prefix("shortcut=") {
+                    Some(keys) => button.shortcut = Some(Shortcut::try_from(keys.to_string())?),
+                    None => return Err(format!("unknown button option '{}'", option)),
+                },
+            }
+        }
+        Ok(button)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Shortcut(pub egui::KeyboardShortcut);
+
+impl JsonSchema for Shortcut {
+    fn schema_name() -> String {
+        "Shortcut".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl TryFrom<String> for Shortcut {
+    type Error = String;
+
+    fn try_from(keys: String) -> Result<Self, Self::Error> {
+        let mut modifiers = egui::Modifiers::NONE;
+        let mut key = None;
+        for part in keys.split('+').map(str::trim) {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers |= egui::Modifiers::CTRL,
+                "cmd" | "command" => modifiers |= egui::Modifiers::COMMAND,
+                "alt" => modifiers |= egui::Modifiers::ALT,
+                "shift" => modifiers |= egui::Modifiers::SHIFT,
+                _ => {
+                    let mut chars = part.chars();
+                    let capitalized = match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    };
+                    key = egui::Key::from_name(part).or(egui::Key::from_name(&capitalized));
+                    if key.is_none() {
+                        return Err(format!("unknown key '{}' in shortcut '{}'", part, keys));
+                    }
+                }
+            }
+        }
+        match key {
+            Some(key) => Ok(Shortcut(egui::KeyboardShortcut::new(modifiers, key))),
+            None => Err(format!("missing key in shortcut '{}'", keys)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Field {
     Label {
+        #[serde(default)]
+        id: Option<String>,
         text: String,
         #[serde(default = "default_font_size")]
         font_size: f32,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Link {
+        #[serde(default)]
+        id: Option<String>,
         label: String,
         url: String,
         #[serde(default = "default_font_size")]
         font_size: f32,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Text {
         id: String,
@@ -48,6 +267,10 @@ pub enum Field {
         text: String,
         #[serde(default)]
         placeholder: String,
+        #[serde(default)]
+        validation: Validation,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Calendar {
         id: String,
@@ -59,11 +282,18 @@ pub enum Field {
         label_pos: LabelPos,
         #[serde(default = "default_font_size")]
         font_size: f32,
-        #[serde(default = "default_date")]
-        #[serde(with = "y_m_d_date_format")]
-        date: NaiveDate,
+        /// Initial date (%Y-%m-%d), today by default
+        #[serde(default, deserialize_with = "y_m_d_date_format::deserialize")]
+        #[schemars(with = "Option<String>")]
+        date: Option<NaiveDate>,
         #[serde(default = "default_date_format")]
         date_format: String,
+        #[serde(default)]
+        validation: Validation,
+        #[serde(skip)]
+        touched: bool,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Password {
         id: String,
@@ -77,6 +307,10 @@ pub enum Field {
         font_size: f32,
         #[serde(default)]
         text: String,
+        #[serde(default)]
+        validation: Validation,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     List {
         id: String,
@@ -89,6 +323,8 @@ pub enum Field {
         values: Vec<String>,
         #[serde(default = "default_font_size")]
         font_size: f32,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Color {
         id: String,
@@ -102,6 +338,10 @@ pub enum Field {
         font_size: f32,
         #[serde(default)]
         rgb: [u8; 3],
+        #[serde(skip)]
+        touched: bool,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Progress {
         id: String,
@@ -111,6 +351,8 @@ pub enum Field {
         label_pos: LabelPos,
         #[serde(default = "default_font_size")]
         font_size: f32,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Check {
         id: String,
@@ -124,6 +366,8 @@ pub enum Field {
         font_size: f32,
         #[serde(default)]
         checked: bool,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Radio {
         id: String,
@@ -137,6 +381,8 @@ pub enum Field {
         font_size: f32,
         selected: String,
         options: Vec<String>,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Slider {
         id: String,
@@ -153,6 +399,12 @@ pub enum Field {
         value: f32,
         #[serde(default)]
         suffix: String,
+        #[serde(default)]
+        validation: Validation,
+        #[serde(skip)]
+        touched: bool,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Combobox {
         id: String,
@@ -165,6 +417,8 @@ pub enum Field {
         options: Vec<String>,
         #[serde(default)]
         selected: String,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
     Image {
         id: String,
@@ -174,10 +428,460 @@ pub enum Field {
         scale: f32,
         #[serde(default = "default_h_align")]
         h_align: HAlign,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
+    Row {
+        #[serde(default)]
+        id: Option<String>,
+        body: Vec<Field>,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
+    Column {
+        #[serde(default)]
+        id: Option<String>,
+        body: Vec<Field>,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
+    Group {
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        title: String,
+        #[serde(default = "default_font_size")]
+        font_size: f32,
+        body: Vec<Field>,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
+    Collapsing {
+        #[serde(default)]
+        id: Option<String>,
+        title: String,
+        #[serde(default)]
+        open: bool,
+        #[serde(default = "default_font_size")]
+        font_size: f32,
+        body: Vec<Field>,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
+    Grid {
+        #[serde(default)]
+        id: Option<String>,
+        columns: usize,
+        #[serde(default)]
+        striped: bool,
+        body: Vec<Field>,
+        #[serde(flatten)]
+        conditions: Conditions,
+    },
+    Tabs {
+        #[serde(default)]
+        id: Option<String>,
+        tabs: Vec<Tab>,
+        #[serde(skip)]
+        selected: usize,
+        #[serde(flatten)]
+        conditions: Conditions,
     },
 }
 
-fn default_date() -> NaiveDate {
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Tab {
+    pub title: String,
+    pub body: Vec<Field>,
+}
+
+impl Field {
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Field::Label { id, .. }
+            | Field::Link { id, .. }
+            | Field::Row { id, .. }
+            | Field::Column { id, .. }
+            | Field::Group { id, .. }
+            | Field::Collapsing { id, .. }
+            | Field::Grid { id, .. }
+            | Field::Tabs { id, .. } => id.as_deref(),
+            Field::Text { id, .. }
+            | Field::Calendar { id, .. }
+            | Field::Password { id, .. }
+            | Field::List { id, .. }
+            | Field::Color { id, .. }
+            | Field::Progress { id, .. }
+            | Field::Check { id, .. }
+            | Field::Radio { id, .. }
+            | Field::Slider { id, .. }
+            | Field::Combobox { id, .. }
+            | Field::Image { id, .. } => Some(id.as_str()),
+        }
+    }
+
+    pub fn conditions(&self) -> &Conditions {
+        match self {
+            Field::Label { conditions, .. }
+            | Field::Link { conditions, .. }
+            | Field::Text { conditions, .. }
+            | Field::Calendar { conditions, .. }
+            | Field::Password { conditions, .. }
+            | Field::List { conditions, .. }
+            | Field::Color { conditions, .. }
+            | Field::Progress { conditions, .. }
+            | Field::Check { conditions, .. }
+            | Field::Radio { conditions, .. }
+            | Field::Slider { conditions, .. }
+            | Field::Combobox { conditions, .. }
+            | Field::Image { conditions, .. }
+            | Field::Row { conditions, .. }
+            | Field::Column { conditions, .. }
+            | Field::Group { conditions, .. }
+            | Field::Collapsing { conditions, .. }
+            | Field::Grid { conditions, .. }
+            | Field::Tabs { conditions, .. } => conditions,
+        }
+    }
+
+    /// Fields inside a container, including every tab of `tabs`
+    pub fn children(&self) -> Vec<&Field> {
+        match self {
+            Field::Row { body, .. }
+            | Field::Column { body, .. }
+            | Field::Group { body, .. }
+            | Field::Collapsing { body, .. }
+            | Field::Grid { body, .. } => body.iter().collect(),
+            Field::Tabs { tabs, .. } => tabs.iter().flat_map(|tab| tab.body.iter()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Field> {
+        match self {
+            Field::Row { body, .. }
+            | Field::Column { body, .. }
+            | Field::Group { body, .. }
+            | Field::Collapsing { body, .. }
+            | Field::Grid { body, .. } => body.iter_mut().collect(),
+            Field::Tabs { tabs, .. } => tabs
+                .iter_mut()
+                .flat_map(|tab| tab.body.iter_mut())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Value of the field as it is printed in the response. Fields without value return `None`.
+    pub fn value(&self) -> Option<String> {
+        match self {
+            Field::Label { .. }
+            | Field::Link { .. }
+            | Field::Progress { .. }
+            | Field::Row { .. }
+            | Field::Column { .. }
+            | Field::Group { .. }
+            | Field::Collapsing { .. }
+            | Field::Grid { .. }
+            | Field::Tabs { .. } => None,
+            Field::Text { text, .. } | Field::Password { text, .. } => Some(text.to_string()),
+            Field::Calendar {
+                date, date_format, ..
+            } => {
+                // An invalid format is an error of the Display impl, `format!` would panic
+                let date = date.unwrap_or_else(default_date);
+                let mut text = String::new();
+                if write!(text, "{}", date.format(date_format)).is_err() {
+                    text = date.format("%Y-%m-%d").to_string();
+                }
+                Some(text)
+            }
+            Field::List { selected, .. }
+            | Field::Radio { selected, .. }
+            | Field::Combobox { selected, .. } => Some(selected.to_string()),
+            Field::Color { rgb, .. } => Some(format!("[{},{},{}]", rgb[0], rgb[1], rgb[2])),
+            Field::Check { checked, .. } => Some(checked.to_string()),
+            Field::Slider { value, .. } => Some(value.to_string()),
+            Field::Image { path, .. } => Some(path.to_string()),
+        }
+    }
+
+    /// Value of the field as native JSON
+    pub fn typed_value(&self) -> Option<Value> {
+        match self {
+            Field::Calendar { date, .. } => Some(Value::String(
+                date.unwrap_or_else(default_date)
+                    .format("%Y-%m-%d")
+                    .to_string(),
+            )),
+            Field::Color { rgb, .. } => Some(json!({ "r": rgb[0], "g": rgb[1], "b": rgb[2] })),
+            Field::Check { checked, .. } => Some(Value::Bool(*checked)),
+            // Through the string to avoid printing the f32 rounding errors
+            Field::Slider { value, .. } => Some(
+                value
+                    .to_string()
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map_or(Value::Null, Value::Number),
+            ),
+            _ => self.value().map(Value::String),
+        }
+    }
+
+    pub fn response_body(&self) -> Option<ResponseBody> {
+        Some(ResponseBody::new(
+            self.id()?.to_string(),
+            self.value()?,
+            self.typed_value()?,
+        ))
+    }
+
+    pub fn is_missing_required(&self) -> bool {
+        match self {
+            Field::Text { required, text, .. } | Field::Password { required, text, .. } => {
+                *required && text.is_empty()
+            }
+            Field::List {
+                required, selected, ..
+            }
+            | Field::Radio {
+                required, selected, ..
+            }
+            | Field::Combobox {
+                required, selected, ..
+            } => *required && selected.is_empty(),
+            Field::Check {
+                required, checked, ..
+            } => *required && !checked,
+            // These fields always have a value, so they are required to be changed by the user
+            Field::Calendar {
+                required, touched, ..
+            }
+            | Field::Color {
+                required, touched, ..
+            }
+            | Field::Slider {
+                required, touched, ..
+            } => *required && !touched,
+            _ => false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.is_missing_required() {
+            return Err("This field is required".to_string());
+        }
+        match self {
+            Field::Text {
+                text, validation, ..
+            }
+            | Field::Password {
+                text, validation, ..
+            } if !text.is_empty() => validation.check(Checked::Text(text)),
+            Field::Calendar {
+                date, validation, ..
+            } => validation.check(Checked::Date(date.unwrap_or_else(default_date))),
+            Field::Slider {
+                value, validation, ..
+            } => validation.check(Checked::Number(*value as f64)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the value of the field. Strings are parsed with the same format used in the
+    /// response, so a value can be given either as native JSON or as the printed string.
+    pub fn set_value(&mut self, value: &Value) -> Result<(), String> {
+        match self {
+            Field::Label { text, .. } => *text = value_to_string(value),
+            Field::Link { url, .. } => *url = value_to_string(value),
+            Field::Text { text, .. } | Field::Password { text, .. } => {
+                *text = value_to_string(value)
+            }
+            Field::Calendar {
+                date,
+                date_format,
+                touched,
+                ..
+            } => {
+                let s = value_to_string(value);
+                let parsed = NaiveDate::parse_from_str(&s, date_format)
+                    .or_else(|_| NaiveDate::parse_from_str(&s, "%Y-%m-%d"))
+                    .map_err(|e| format!("invalid date '{}': {}", s, e))?;
+                *date = Some(parsed);
+                *touched = true;
+            }
+            Field::List {
+                selected, values, ..
+            } => *selected = one_of(value, values)?,
+            Field::Radio {
+                selected, options, ..
+            }
+            | Field::Combobox {
+                selected, options, ..
+            } => *selected = one_of(value, options)?,
+            Field::Color { rgb, touched, .. } => {
+                *rgb = parse_rgb(value)?;
+                *touched = true;
+            }
+            Field::Check { checked, .. } => {
+                *checked = match value {
+                    Value::Bool(b) => *b,
+                    v => value_to_string(v)
+                        .parse()
+                        .map_err(|_| format!("invalid boolean '{}'", v))?,
+                }
+            }
+            Field::Slider {
+                min,
+                max,
+                value: v,
+                touched,
+                ..
+            } => {
+                let n = parse_number(value)?;
+                *v = n.clamp(*min, *max);
+                *touched = true;
+            }
+            Field::Image { path, .. } => {
+                let uri = value_to_string(value);
+                if let Some(error) = check::check_image(&uri) {
+                    return Err(error);
+                }
+                *path = uri;
+            }
+            Field::Progress { .. } => return Err("progress has no value to set".to_string()),
+            Field::Row { .. }
+            | Field::Column { .. }
+            | Field::Group { .. }
+            | Field::Collapsing { .. }
+            | Field::Grid { .. }
+            | Field::Tabs { .. } => return Err("containers have no value".to_string()),
+        }
+        Ok(())
+    }
+
+    pub fn set_label(&mut self, new_label: String) -> Result<(), String> {
+        match self {
+            Field::Label { text, .. } => *text = new_label,
+            Field::List { header, .. } => *header = new_label,
+            Field::Group { title, .. } | Field::Collapsing { title, .. } => *title = new_label,
+            Field::Link { label, .. }
+            | Field::Text { label, .. }
+            | Field::Calendar { label, .. }
+            | Field::Password { label, .. }
+            | Field::Color { label, .. }
+            | Field::Progress { label, .. }
+            | Field::Check { label, .. }
+            | Field::Radio { label, .. }
+            | Field::Slider { label, .. }
+            | Field::Combobox { label, .. } => *label = new_label,
+            Field::Image { .. }
+            | Field::Row { .. }
+            | Field::Column { .. }
+            | Field::Grid { .. }
+            | Field::Tabs { .. } => return Err("this field has no label".to_string()),
+        }
+        Ok(())
+    }
+}
+
+/// Every field of the layout, including the ones inside containers, in order.
+pub fn flatten(fields: &[Field]) -> Vec<&Field> {
+    collect_fields(fields.iter().collect(), &|_| true)
+}
+
+/// Like [`flatten`], but skipping hidden fields and everything inside hidden containers.
+pub fn visible_fields<'a>(fields: &'a [Field], values: &HashMap<String, String>) -> Vec<&'a Field> {
+    collect_fields(fields.iter().collect(), &|field| {
+        field.conditions().is_visible(values)
+    })
+}
+
+fn collect_fields<'a>(fields: Vec<&'a Field>, keep: &dyn Fn(&Field) -> bool) -> Vec<&'a Field> {
+    let mut collected = Vec::new();
+    for field in fields {
+        if keep(field) {
+            collected.push(field);
+            collected.extend(collect_fields(field.children(), keep));
+        }
+    }
+    collected
+}
+
+pub fn find_field_mut<'a>(fields: &'a mut [Field], id: &str) -> Option<&'a mut Field> {
+    find_in(fields.iter_mut().collect(), id)
+}
+
+fn find_in<'a>(fields: Vec<&'a mut Field>, id: &str) -> Option<&'a mut Field> {
+    for field in fields {
+        if field.id() == Some(id) {
+            return Some(field);
+        }
+        if let Some(found) = find_in(field.children_mut(), id) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_string(),
+        v => v.to_string(),
+    }
+}
+
+fn one_of(value: &Value, options: &[String]) -> Result<String, String> {
+    let s = value_to_string(value);
+    if s.is_empty() || options.contains(&s) {
+        Ok(s)
+    } else {
+        Err(format!("'{}' is not one of the options", s))
+    }
+}
+
+fn parse_number(value: &Value) -> Result<f32, String> {
+    match value {
+        Value::Number(n) => n.as_f64().map(|n| n as f32),
+        v => value_to_string(v).trim().parse().ok(),
+    }
+    .ok_or(format!("invalid number '{}'", value))
+}
+
+fn parse_rgb(value: &Value) -> Result<[u8; 3], String> {
+    let parsed = match value {
+        Value::String(s) if s.starts_with('#') && s.len() == 7 && s.is_ascii() => {
+            let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).ok();
+            match (channel(1), channel(3), channel(5)) {
+                (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+                _ => None,
+            }
+        }
+        Value::String(s) => serde_json::from_str(s).ok(),
+        // The format of the version 2 of the response
+        Value::Object(map) => {
+            let channel = |c: &str| map.get(c)?.as_u64()?.try_into().ok();
+            match (channel("r"), channel("g"), channel("b")) {
+                (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+                _ => None,
+            }
+        }
+        v => serde_json::from_value(v.clone()).ok(),
+    };
+    parsed.ok_or(format!("invalid color '{}'", value))
+}
+
+fn default_button_result() -> ButtonResult {
+    ButtonResult::Ok
+}
+fn default_timeout_action() -> TimeoutAction {
+    TimeoutAction::Cancel
+}
+fn default_true() -> bool {
+    true
+}
+pub fn default_date() -> NaiveDate {
     let now = Utc::now();
     NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap()
 }
@@ -213,15 +917,21 @@ mod y_m_d_date_format {
 
     const FORMAT: &'static str = "%Y-%m-%d";
 
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
+        let s = match Option::<String>::deserialize(deserializer)? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
 
         match chrono::NaiveDate::parse_from_str(&s, FORMAT) {
-            Ok(naive_date) => Ok(naive_date),
-            Err(msg) => Err(serde::de::Error::custom(msg)),
+            Ok(naive_date) => Ok(Some(naive_date)),
+            Err(msg) => Err(serde::de::Error::custom(format!(
+                "invalid date '{}': {}",
+                s, msg
+            ))),
         }
     }
 }
@@ -3,32 +3,47 @@ use egui::Vec2;
 use egui::Widget;
 use egui_extras::{Column, TableBuilder};
 use mpsc::{Receiver, Sender};
+use schemars::JsonSchema;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
-use crate::custom_dialog::Field;
+use crate::custom_dialog::{self, Button, ButtonResult, CustomDialog, Field, Page, TimeoutAction};
+use crate::response::Ack;
+use crate::response::Event;
 use crate::response::Response;
 use crate::response::ResponseBody;
 
 pub struct GUI {
-    custom_dialog_fields: Vec<Field>,
-    ok_pressed: bool,
+    pages: Vec<Page>,
+    page: usize,
+    wizard: bool,
+    buttons: Vec<Button>,
+    custom_buttons: bool,
+    pressed: Option<Button>,
     rx: Receiver<UserInput>,
     progress: f32,
+    events: bool,
+    last_values: Vec<ResponseBody>,
+    show_errors: bool,
+    deadline: Option<Instant>,
+    timeout_action: TimeoutAction,
+    countdown: bool,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum LabelPos {
     Over,
     Next,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HLabelPos {
     Before,
     After,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HAlign {
     Left,
@@ -36,21 +51,130 @@ pub enum HAlign {
     Right,
 }
 
-enum UserInput {
-    Progress(f32),
+/// Commands received from stdin while the dialog is open. Every line is a JSON object
+/// tagged by `cmd`, e.g. `{"cmd":"set","id":"name","value":"x"}`. The legacy
+/// `progress-NN` lines are still understood.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum UserInput {
+    Progress {
+        value: f32,
+    },
+    Set {
+        id: String,
+        value: serde_json::Value,
+    },
+    SetLabel {
+        id: String,
+        label: String,
+    },
+    Close {
+        #[serde(default = "default_close_result")]
+        result: CloseResult,
+    },
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseResult {
+    Ok,
+    Cancel,
+}
+
+fn default_close_result() -> CloseResult {
+    CloseResult::Cancel
+}
+
+/// Reads the commands from stdin until it is closed or `handle` returns false. The lines which
+/// can't be parsed are answered with an error ack.
+pub fn read_user_input(mut handle: impl FnMut(UserInput) -> bool) {
+    let stdin = std::io::stdin();
+    let mut user_input = String::new();
+    let progress_regex = regex::Regex::new("^progress-([0-9]+)$").unwrap();
+
+    loop {
+        user_input.clear();
+        match stdin.read_line(&mut user_input) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(error) => {
+                eprintln!("error reading user input: {error}");
+                break;
+            }
+        }
+
+        let line = user_input.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let parsed = match progress_regex.captures(line) {
+            Some(captured_progress) => captured_progress[1]
+                .parse()
+                .map(|value| UserInput::Progress { value })
+                .map_err(|e| e.to_string()),
+            None => serde_json::from_str(line).map_err(|e| e.to_string()),
+        };
+
+        match parsed {
+            Ok(input) => {
+                if !handle(input) {
+                    break;
+                }
+            }
+            Err(error) => Ack::error("unknown", None, error),
+        }
+    }
 }
 
 impl GUI {
-    pub fn new(custom_dialog_fields: Vec<Field>) -> Self {
+    pub fn new(custom_dialog: CustomDialog, ctx: &egui::Context) -> Self {
         let (tx, rx): (Sender<UserInput>, Receiver<UserInput>) = mpsc::channel();
 
-        Self::handle_user_input(tx);
-        GUI {
-            custom_dialog_fields,
-            ok_pressed: false,
+        Self::handle_user_input(tx, ctx.clone());
+        let wizard = !custom_dialog.pages.is_empty();
+        let pages = if wizard {
+            custom_dialog.pages
+        } else {
+            vec![Page {
+                title: None,
+                body: custom_dialog.body,
+                skip_if: None,
+            }]
+        };
+        let custom_buttons = !custom_dialog.buttons.is_empty();
+        let buttons = if custom_buttons {
+            custom_dialog.buttons
+        } else {
+            vec![
+                Button::ok(if wizard { "Finish" } else { "Ok" }),
+                Button::cancel(),
+            ]
+        };
+        let mut gui = GUI {
+            pages,
+            page: 0,
+            wizard,
+            buttons,
+            custom_buttons,
+            pressed: None,
             rx,
             progress: 0f32,
+            events: custom_dialog.events,
+            last_values: Vec::new(),
+            show_errors: false,
+            deadline: custom_dialog
+                .timeout
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+            timeout_action: custom_dialog.timeout_action,
+            countdown: custom_dialog.countdown,
+        };
+        gui.last_values = gui.current_values();
+        let values = gui.values();
+        if gui.is_skipped(0, &values) {
+            gui.page = gui.next_page(&values).unwrap_or(0);
         }
+        gui
     }
 
     pub fn add_labeled_widget(
@@ -60,13 +184,12 @@ impl GUI {
         font_size: f32,
         widget: impl Widget,
         mark_as_required: bool,
-    ) {
+    ) -> egui::Response {
         if label_text.len() == 0 || label_text == "null" {
             if mark_as_required {
                 ui.visuals_mut().extreme_bg_color = egui::Color32::LIGHT_RED;
             }
-            ui.add(widget);
-            return;
+            return ui.add(widget);
         }
 
         match label_pos {
@@ -80,8 +203,9 @@ impl GUI {
                         egui::RichText::new(label_text.as_str()).size(font_size)
                     };
                     ui.label(label_w);
-                    ui.add(widget);
-                });
+                    ui.add(widget)
+                })
+                .inner
             }
             LabelPos::Next => {
                 ui.horizontal(|ui| {
@@ -93,40 +217,159 @@ impl GUI {
                         egui::RichText::new(label_text.as_str()).size(font_size)
                     };
                     ui.label(label_w);
-                    ui.add(widget);
-                });
+                    ui.add(widget)
+                })
+                .inner
             }
         }
     }
 
-    fn handle_user_input(tx: Sender<UserInput>) {
+    fn handle_user_input(tx: Sender<UserInput>, ctx: egui::Context) {
         std::thread::spawn(move || {
-            let stdin = std::io::stdin();
-            let mut user_input = String::new();
-            let progress_regex = regex::Regex::new("progress-([0-9]+)").unwrap();
-            let sleep_duration = std::time::Duration::from_millis(100);
-
-            loop {
-                std::thread::sleep(sleep_duration);
-                user_input.clear();
-                match stdin.read_line(&mut user_input) {
-                    Ok(_) => {}
-                    Err(error) => println!("error reading user input: {error}"),
+            read_user_input(|input| {
+                if tx.send(input).is_err() {
+                    return false;
                 }
+                ctx.request_repaint();
+                true
+            });
+        });
+    }
 
-                user_input.retain(|c| c.is_ascii());
+    fn apply_user_input(&mut self, ctx: &egui::Context, user_input: UserInput) {
+        match user_input {
+            UserInput::Progress { value } => {
+                self.progress = value;
+            }
+            UserInput::Set { id, value } => {
+                let result = match self.find_field(&id) {
+                    Some(Field::Progress { .. }) => match value.as_f64() {
+                        Some(p) => {
+                            self.progress = p as f32;
+                            Ok(())
+                        }
+                        None => Err(format!("invalid progress '{}'", value)),
+                    },
+                    Some(field) => field.set_value(&value),
+                    None => Err(format!("no field with id '{}'", id)),
+                };
+                Ack::result("set", Some(id), result);
+            }
+            UserInput::SetLabel { id, label } => {
+                let result = match self.find_field(&id) {
+                    Some(field) => field.set_label(label),
+                    None => Err(format!("no field with id '{}'", id)),
+                };
+                Ack::result("set_label", Some(id), result);
+            }
+            UserInput::Close { result } => {
+                let button = match result {
+                    CloseResult::Ok => Some(
+                        self.find_button(|b| b.result == ButtonResult::Ok && b.default)
+                            .or(self.find_button(|b| b.result == ButtonResult::Ok))
+                            .unwrap_or(Button::ok("Ok")),
+                    ),
+                    CloseResult::Cancel => self
+                        .find_button(|b| b.result == ButtonResult::Cancel && b.escape)
+                        .or(self.find_button(|b| b.result == ButtonResult::Cancel)),
+                };
+                Ack::result("close", None, Ok(()));
+                self.press(ctx, button);
+            }
+        }
+    }
 
-                match progress_regex.captures(user_input.as_str()) {
-                    Some(captured_progress) => {
-                        let p = captured_progress.get(1).unwrap().as_str();
-                        tx.send(UserInput::Progress(p.parse().unwrap())).unwrap();
-                    }
-                    None => {
-                        println!("not captured -> {:?}", user_input)
-                    }
-                }
+    fn current_values(&self) -> Vec<ResponseBody> {
+        self.pages
+            .iter()
+            .flat_map(|page| custom_dialog::flatten(&page.body))
+            .filter_map(|field| field.response_body())
+            .collect()
+    }
+
+    fn values(&self) -> HashMap<String, String> {
+        self.current_values()
+            .into_iter()
+            .map(|body| (body.id, body.value))
+            .collect()
+    }
+
+    /// Prints a change event for every field whose value differs from the last frame.
+    fn emit_change_events(&mut self) {
+        let values = self.current_values();
+        for (old, new) in self.last_values.iter().zip(values.iter()) {
+            if old.value != new.value {
+                Event::change(new, old);
             }
-        });
+        }
+        self.last_values = values;
+    }
+
+    fn find_field(&mut self, id: &str) -> Option<&mut Field> {
+        self.pages
+            .iter_mut()
+            .find_map(|page| custom_dialog::find_field_mut(&mut page.body, id))
+    }
+
+    fn find_button(&self, predicate: impl Fn(&Button) -> bool) -> Option<Button> {
+        self.buttons.iter().find(|b| predicate(b)).cloned()
+    }
+
+    /// Closes the dialog with the given button. Without button, the dialog is cancelled
+    fn press(&mut self, ctx: &egui::Context, button: Option<Button>) {
+        self.pressed = button;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+    }
+
+    fn is_skipped(&self, page: usize, values: &HashMap<String, String>) -> bool {
+        self.pages[page]
+            .skip_if
+            .as_ref()
+            .is_some_and(|condition| condition.eval(values))
+    }
+
+    fn next_page(&self, values: &HashMap<String, String>) -> Option<usize> {
+        (self.page + 1..self.pages.len()).find(|page| !self.is_skipped(*page, values))
+    }
+
+    fn previous_page(&self, values: &HashMap<String, String>) -> Option<usize> {
+        (0..self.page)
+            .rev()
+            .find(|page| !self.is_skipped(*page, values))
+    }
+
+    fn page_is_valid(&self, page: usize, values: &HashMap<String, String>) -> bool {
+        custom_dialog::visible_fields(&self.pages[page].body, values)
+            .iter()
+            .all(|field| field.validate().is_ok())
+    }
+
+    /// Values of the visible fields of every page which is not skipped
+    fn output(&self, values: &HashMap<String, String>) -> Vec<ResponseBody> {
+        (0..self.pages.len())
+            .filter(|page| !self.is_skipped(*page, values))
+            .flat_map(|page| custom_dialog::visible_fields(&self.pages[page].body, values))
+            .filter_map(|field| field.response_body())
+            .collect()
+    }
+
+    fn time_out(&self) {
+        match self.timeout_action {
+            TimeoutAction::Cancel => Response::timeout(),
+            TimeoutAction::Accept => Response::ok(self.output(&self.values())),
+        }
+    }
+
+    /// Moves to the next page if every field of the current one is valid
+    fn go_next(&mut self, values: &HashMap<String, String>) {
+        if !self.page_is_valid(self.page, values) {
+            self.show_errors = true;
+            return;
+        }
+        if let Some(next) = self.next_page(values) {
+            self.page = next;
+            self.show_errors = false;
+        }
     }
 }
 
@@ -141,22 +384,57 @@ impl eframe::App for GUI {
         let is_shift = ctx.input(|i| i.modifiers.shift);
         let is_enter = ctx.input(|i| i.key_released(egui::Key::Enter));
 
+        let is_escape = ctx.input(|i| i.key_released(egui::Key::Escape));
+        if is_escape {
+            self.press(ctx, self.find_button(|b| b.escape));
+        }
+        while let Ok(user_input) = self.rx.try_recv() {
+            self.apply_user_input(ctx, user_input);
+        }
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if let Some(remaining) = remaining {
+            if remaining.is_zero() {
+                self.time_out();
+            }
+            // Wake up every second to update the countdown, even without input
+            ctx.request_repaint_after(remaining.min(Duration::from_secs(1)));
+        }
+        let countdown = remaining.filter(|_| self.countdown);
+        let values = self.values();
+        let next_page = self.next_page(&values);
+        let previous_page = self.previous_page(&values);
+
         if is_enter && !is_shift {
-            self.ok_pressed = true;
-            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            if next_page.is_some() {
+                self.go_next(&values);
+            } else if let Some(button) = self.find_button(|b| b.default) {
+                self.press(ctx, Some(button));
+            }
         }
 
-        let is_escape = ctx.input(|i| i.key_released(egui::Key::Escape));
-        if is_escape {
-            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        let shortcut = self.buttons.iter().find(|b| {
+            b.shortcut
+                .is_some_and(|s| ctx.input_mut(|i| i.consume_shortcut(&s.0)))
+        });
+        if let Some(button) = shortcut.cloned() {
+            self.press(ctx, Some(button));
         }
-        match self.rx.try_recv() {
-            Ok(user_input) => match user_input {
-                UserInput::Progress(progress) => {
-                    self.progress = progress;
-                }
-            },
-            Err(_) => {}
+
+        // Before the last page of a wizard, only the buttons which cancel it are shown
+        let buttons: Vec<Button> = self
+            .buttons
+            .iter()
+            .filter(|b| next_page.is_none() || b.result == ButtonResult::Cancel)
+            .cloned()
+            .collect();
+        let mut clicked = None;
+
+        let painter = FieldPainter {
+            values: &values,
+            progress: self.progress,
+            show_errors: self.show_errors,
         };
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::warn_if_debug_build(ui);
@@ -165,385 +443,66 @@ impl eframe::App for GUI {
             egui::ScrollArea::vertical()
                 .max_height(window_size.y - bottom_line_height)
                 .show(ui, |ui| {
-                    for (index, field) in self.custom_dialog_fields.iter_mut().enumerate() {
-                        ui.push_id(index, |ui| match field {
-                            Field::Label { text, font_size } => {
-                                ui.label(egui::RichText::new(text.as_str()).size(*font_size));
-                            }
-                            Field::Link {
-                                label,
-                                url,
-                                font_size,
-                            } => {
-                                ui.hyperlink_to(
-                                    egui::RichText::new(label.as_str()).size(*font_size),
-                                    url,
-                                );
-                            }
-                            Field::Text {
-                                id: _,
-                                required,
-                                multiline,
-                                label,
-                                label_pos,
-                                font_size,
-                                text,
-                                placeholder,
-                            } => {
-                                let mark_as_required = *required && text.len() == 0;
-                                let text_edit = if *multiline {
-                                    egui::TextEdit::multiline(text)
-                                        .desired_width(window_size.x)
-                                        .font(egui::FontId::new(
-                                            *font_size,
-                                            egui::FontFamily::Proportional,
-                                        ))
-                                } else {
-                                    egui::TextEdit::singleline(text)
-                                        .desired_width(window_size.x)
-                                        .font(egui::FontId::new(
-                                            *font_size,
-                                            egui::FontFamily::Proportional,
-                                        ))
-                                };
-                                GUI::add_labeled_widget(
-                                    ui,
-                                    &label,
-                                    &label_pos,
-                                    *font_size,
-                                    text_edit.hint_text(placeholder.as_str()),
-                                    mark_as_required,
-                                );
-                            }
-                            Field::Calendar {
-                                id: _,
-                                required: _,
-                                label,
-                                label_pos,
-                                font_size,
-                                date,
-                                date_format: _,
-                            } => {
-                                GUI::add_labeled_widget(
-                                    ui,
-                                    &label,
-                                    &label_pos,
-                                    *font_size,
-                                    egui_extras::DatePickerButton::new(date),
-                                    false,
-                                );
-                            }
-                            Field::Password {
-                                id: _,
-                                required,
-                                label,
-                                label_pos,
-                                font_size,
-                                text,
-                            } => {
-                                let mark_as_required = *required && text.len() == 0;
-                                GUI::add_labeled_widget(
-                                    ui,
-                                    &label,
-                                    &label_pos,
-                                    *font_size,
-                                    egui::TextEdit::singleline(text)
-                                        .password(true)
-                                        .desired_width(window_size.x)
-                                        .font(egui::FontId::new(
-                                            *font_size,
-                                            egui::FontFamily::Proportional,
-                                        )),
-                                    mark_as_required,
-                                );
-                            }
-                            Field::List {
-                                id: _,
-                                required,
-                                header,
-                                selected,
-                                values,
-                                font_size,
-                            } => {
-                                TableBuilder::new(ui)
-                                    .striped(true)
-                                    .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-                                    .column(Column::remainder().at_least(100.0))
-                                    .header(20.0, |mut row| {
-                                        row.col(|ui| {
-                                            let heading = if *required && selected.len() == 0 {
-                                                egui::RichText::new(header.as_str())
-                                                    .size(*font_size)
-                                                    .color(egui::Color32::RED)
-                                            } else {
-                                                egui::RichText::new(header.as_str())
-                                                    .size(*font_size)
-                                            };
-                                            ui.heading(heading);
-                                        });
-                                    })
-                                    .body(|mut body| {
-                                        for v in values.iter() {
-                                            body.row(18.0, |mut row| {
-                                                row.col(|ui| {
-                                                    ui.selectable_value(
-                                                        selected,
-                                                        v.to_string(),
-                                                        egui::RichText::new(v.as_str())
-                                                            .size(*font_size),
-                                                    );
-                                                });
-                                            });
-                                        }
-                                    });
-                            }
-                            Field::Color {
-                                id: _,
-                                required: _,
-                                label,
-                                label_pos,
-                                font_size,
-                                rgb,
-                            } => match label_pos {
-                                LabelPos::Over => {
-                                    ui.vertical(|ui| {
-                                        ui.label(
-                                            egui::RichText::new(label.as_str()).size(*font_size),
-                                        );
-                                        egui::widgets::color_picker::color_edit_button_srgb(
-                                            ui, rgb,
-                                        );
-                                    });
-                                }
-                                LabelPos::Next => {
-                                    ui.horizontal(|ui| {
-                                        ui.label(label.as_str());
-                                        egui::widgets::color_picker::color_edit_button_srgb(
-                                            ui, rgb,
-                                        );
-                                    });
-                                }
-                            },
-                            Field::Progress {
-                                id: _,
-                                label,
-                                label_pos,
-                                font_size,
-                            } => {
-                                match label_pos {
-                                    LabelPos::Over => {
-                                        ui.vertical(|ui| {
-                                            ui.label(
-                                                egui::RichText::new(label.as_str())
-                                                    .size(*font_size),
-                                            );
-                                            ui.add(
-                                                egui::ProgressBar::new(self.progress / 100.)
-                                                    .show_percentage()
-                                                    .desired_width(window_size.x),
-                                            );
-                                            // ui.add(egui::Spinner::new());
-                                        });
-                                    }
-                                    LabelPos::Next => {
-                                        ui.horizontal(|ui| {
-                                            ui.label(
-                                                egui::RichText::new(label.as_str())
-                                                    .size(*font_size),
-                                            );
-                                            ui.add(
-                                                egui::ProgressBar::new(self.progress / 100.)
-                                                    .show_percentage()
-                                                    .desired_width(window_size.x),
-                                            );
-                                            ui.add(egui::Spinner::new());
-                                        });
-                                    }
-                                }
-                                ctx.request_repaint();
-                            }
-                            Field::Check {
-                                id: _,
-                                required,
-                                label,
-                                label_pos,
-                                font_size,
-                                checked,
-                            } => match label_pos {
-                                HLabelPos::Before => {
-                                    ui.horizontal(|ui| {
-                                        let label_w = if *required && !*checked {
-                                            egui::RichText::new(label.as_str())
-                                                .size(*font_size)
-                                                .color(egui::Color32::RED)
-                                        } else {
-                                            egui::RichText::new(label.as_str()).size(*font_size)
-                                        };
-                                        ui.label(label_w);
-                                        ui.checkbox(checked, "");
-                                    });
-                                }
-                                HLabelPos::After => {
-                                    let label_w = if *required && !*checked {
-                                        egui::RichText::new(label.as_str())
-                                            .size(*font_size)
-                                            .color(egui::Color32::RED)
-                                    } else {
-                                        egui::RichText::new(label.as_str()).size(*font_size)
-                                    };
-                                    ui.checkbox(checked, label_w);
-                                }
-                            },
-                            Field::Radio {
-                                id: _,
-                                required,
-                                label,
-                                label_pos,
-                                font_size,
-                                selected,
-                                options,
-                            } => match label_pos {
-                                LabelPos::Over => {
-                                    ui.vertical(|ui| {
-                                        let label_w = if *required && selected.len() == 0 {
-                                            egui::RichText::new(label.as_str())
-                                                .size(*font_size)
-                                                .color(egui::Color32::RED)
-                                        } else {
-                                            egui::RichText::new(label.as_str()).size(*font_size)
-                                        };
-                                        ui.label(label_w);
-                                        for opt in options.iter() {
-                                            ui.radio_value(
-                                                selected,
-                                                opt.to_string(),
-                                                egui::RichText::new(opt.as_str()).size(*font_size),
-                                            );
-                                        }
-                                    });
-                                }
-                                LabelPos::Next => {
-                                    ui.horizontal(|ui| {
-                                        if *required && selected.len() == 0 {
-                                            ui.label(
-                                                egui::RichText::new(label.as_str())
-                                                    .color(egui::Color32::RED),
-                                            );
-                                        } else {
-                                            ui.label(label.as_str());
-                                        }
-                                        for opt in options.iter() {
-                                            ui.radio_value(
-                                                selected,
-                                                opt.to_string(),
-                                                egui::RichText::new(opt.as_str()).size(*font_size),
-                                            );
-                                        }
-                                    });
-                                }
-                            },
-                            Field::Slider {
-                                id: _,
-                                required: _,
-                                label,
-                                label_pos,
-                                font_size,
-                                min,
-                                max,
-                                value,
-                                suffix,
-                            } => {
-                                GUI::add_labeled_widget(
-                                    ui,
-                                    &label,
-                                    &label_pos,
-                                    *font_size,
-                                    egui::Slider::new(value, *min..=*max).suffix(suffix.as_str()),
-                                    false,
-                                );
-                            }
-                            Field::Combobox {
-                                id: _,
-                                required,
-                                label,
-                                font_size,
-                                options,
-                                selected,
-                            } => {
-                                let label_w = if *required && selected.len() == 0 {
-                                    egui::RichText::new(label.as_str())
-                                        .size(*font_size)
-                                        .color(egui::Color32::RED)
-                                } else {
-                                    egui::RichText::new(label.as_str()).size(*font_size)
-                                };
-                                let combo = egui::ComboBox::from_label(label_w);
-                                combo.selected_text(selected.as_str()).show_ui(ui, |ui| {
-                                    for opt in options {
-                                        ui.selectable_value(
-                                            selected,
-                                            opt.to_string(),
-                                            egui::RichText::new(opt.as_str()).size(*font_size),
-                                        );
-                                    }
-                                });
-                            }
-                            Field::Image {
-                                id: _,
-                                path,
-                                scale,
-                                h_align,
-                            } => {
-                                ui.horizontal(|ui| {
-                                    let img = egui::Image::new(path.to_string())
-                                        .fit_to_original_size(*scale);
-                                    let tpoll =
-                                        img.load_for_size(ui.ctx(), ui.available_size()).unwrap();
-                                    let mut img_w = 0.;
-
-                                    match tpoll.size() {
-                                        Some(s) => {
-                                            img_w = s.x * *scale;
-                                        }
-                                        None => {}
-                                    }
-                                    let space = match h_align {
-                                        HAlign::Left => 0.,
-                                        HAlign::Center => {
-                                            (window_size.x - ui.spacing().item_spacing.x - img_w)
-                                                * 0.5
-                                        }
-                                        HAlign::Right => {
-                                            window_size.x - ui.spacing().item_spacing.x - img_w
-                                        }
-                                    };
-
-                                    ui.add_space(space);
-                                    ui.add(img);
-                                });
-                            }
-                        });
+                    let page_count = self.pages.len();
+                    let page = &mut self.pages[self.page];
+                    if self.wizard {
+                        let title = match &page.title {
+                            Some(title) => format!("{} ({}/{})", title, self.page + 1, page_count),
+                            None => format!("{}/{}", self.page + 1, page_count),
+                        };
+                        ui.heading(title);
                     }
+                    painter.show_fields(ui, &mut page.body);
                 });
             ui.with_layout(egui::Layout::bottom_up(egui::Align::BOTTOM), |ui| {
                 ui.set_max_size(Vec2::new(window_size.x, 30.0));
                 ui.vertical(|ui| {
                     ui.separator();
                     ui.horizontal_centered(|ui| {
-                        let buttons_width = 80.;
+                        let mut buttons_count = buttons.len();
+                        if self.wizard {
+                            buttons_count += 1;
+                        }
+                        if next_page.is_some() {
+                            buttons_count += 1;
+                        }
+                        if countdown.is_some() {
+                            buttons_count += 1;
+                        }
+                        let buttons_width = 40. * buttons_count as f32;
                         ui.add_space((window_size.x - buttons_width) * 0.5);
-                        if ui.button("Ok").clicked() {
-                            self.ok_pressed = true;
-                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                        if let Some(remaining) = countdown {
+                            ui.label(format!("{}s", remaining.as_secs_f32().ceil()));
                         }
-                        if ui.button("Cancel").clicked() {
-                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                        if self.wizard {
+                            let back =
+                                ui.add_enabled(previous_page.is_some(), egui::Button::new("Back"));
+                            if back.clicked() {
+                                self.page = previous_page.unwrap_or(self.page);
+                                self.show_errors = false;
+                            }
+                        }
+                        if next_page.is_some() && ui.button("Next").clicked() {
+                            self.go_next(&values);
+                        }
+                        for button in buttons.iter() {
+                            if ui.button(button.label.as_str()).clicked() {
+                                clicked = Some(button.clone());
+                            }
                         }
                     });
                 });
             });
         });
 
+        if clicked.is_some() {
+            self.press(ctx, clicked);
+        }
+
+        if self.events {
+            self.emit_change_events();
+        }
+
         let close_requested = ctx.input(|i| i.viewport().close_requested());
 
         if close_requested && !confirm_close(self) {
@@ -560,184 +519,564 @@ impl eframe::App for GUI {
     }
 }
 
-fn confirm_close(gui: &mut GUI) -> bool {
-    if !gui.ok_pressed {
-        Response::cancel();
-        return true;
+/// Draws the fields of the dialog, recursing into containers.
+struct FieldPainter<'a> {
+    values: &'a HashMap<String, String>,
+    progress: f32,
+    show_errors: bool,
+}
+
+impl FieldPainter<'_> {
+    fn show_fields(&self, ui: &mut Ui, fields: &mut [Field]) {
+        for (index, field) in fields.iter_mut().enumerate() {
+            self.show_entry(ui, index, field);
+        }
     }
 
-    let mut close_window = true;
-    let out: Vec<ResponseBody> = gui
-        .custom_dialog_fields
-        .iter()
-        .filter_map(|field| match field {
+    /// Draws the field with its validation error, if it is visible. Returns whether it was drawn.
+    fn show_entry(&self, ui: &mut Ui, index: usize, field: &mut Field) -> bool {
+        let conditions = field.conditions();
+        if !conditions.is_visible(self.values) {
+            return false;
+        }
+        let enabled = conditions.is_enabled(self.values);
+        ui.add_enabled_ui(enabled, |ui| {
+            ui.push_id(index, |ui| {
+                ui.vertical(|ui| {
+                    self.show_field(ui, field);
+                    // Missing required values are already marked, the message is shown
+                    // once the user tries to accept the dialog
+                    if let Err(error) = field.validate() {
+                        if self.show_errors || !field.is_missing_required() {
+                            ui.label(egui::RichText::new(error).small().color(egui::Color32::RED));
+                        }
+                    }
+                })
+            })
+        });
+        true
+    }
+
+    fn show_field(&self, ui: &mut Ui, field: &mut Field) {
+        match field {
             Field::Label {
-                text: _,
-                font_size: _,
-            } => None,
+                id: _,
+                text,
+                font_size,
+                conditions: _,
+            } => {
+                ui.label(egui::RichText::new(text.as_str()).size(*font_size));
+            }
             Field::Link {
-                label: _,
-                url: _,
-                font_size: _,
-            } => None,
+                id: _,
+                label,
+                url,
+                font_size,
+                conditions: _,
+            } => {
+                ui.hyperlink_to(egui::RichText::new(label.as_str()).size(*font_size), url);
+            }
             Field::Text {
-                id,
+                id: _,
                 required,
-                multiline: _,
-                label: _,
-                label_pos: _,
-                font_size: _,
+                multiline,
+                label,
+                label_pos,
+                font_size,
                 text,
-                placeholder: _,
+                placeholder,
+                validation: _,
+                conditions: _,
             } => {
-                if *required && text.len() == 0 {
-                    close_window = false;
-                }
-                Some(ResponseBody {
-                    id: id.to_string(),
-                    value: text.to_string(),
-                })
+                let mark_as_required = *required && text.len() == 0;
+                let text_edit = if *multiline {
+                    egui::TextEdit::multiline(text)
+                        .desired_width(ui.available_width())
+                        .font(egui::FontId::new(
+                            *font_size,
+                            egui::FontFamily::Proportional,
+                        ))
+                } else {
+                    egui::TextEdit::singleline(text)
+                        .desired_width(ui.available_width())
+                        .font(egui::FontId::new(
+                            *font_size,
+                            egui::FontFamily::Proportional,
+                        ))
+                };
+                GUI::add_labeled_widget(
+                    ui,
+                    &label,
+                    &label_pos,
+                    *font_size,
+                    text_edit.hint_text(placeholder.as_str()),
+                    mark_as_required,
+                );
             }
             Field::Calendar {
-                id,
-                required: _,
-                label: _,
-                label_pos: _,
-                font_size: _,
+                id: _,
+                required,
+                label,
+                label_pos,
+                font_size,
                 date,
-                date_format,
-            } => Some(ResponseBody {
-                id: id.to_string(),
-                value: format!("{}", date.format(&date_format)),
-            }),
+                date_format: _,
+                validation: _,
+                touched,
+                conditions: _,
+            } => {
+                let mark_as_required = *required && !*touched;
+                if GUI::add_labeled_widget(
+                    ui,
+                    &label,
+                    &label_pos,
+                    *font_size,
+                    egui_extras::DatePickerButton::new(
+                        date.get_or_insert_with(custom_dialog::default_date),
+                    ),
+                    mark_as_required,
+                )
+                .changed()
+                {
+                    *touched = true;
+                }
+            }
             Field::Password {
-                id,
+                id: _,
                 required,
-                label: _,
-                label_pos: _,
-                font_size: _,
+                label,
+                label_pos,
+                font_size,
                 text,
+                validation: _,
+                conditions: _,
             } => {
-                if *required && text.len() == 0 {
-                    close_window = false;
-                }
-                Some(ResponseBody {
-                    id: id.to_string(),
-                    value: text.to_string(),
-                })
+                let mark_as_required = *required && text.len() == 0;
+                GUI::add_labeled_widget(
+                    ui,
+                    &label,
+                    &label_pos,
+                    *font_size,
+                    egui::TextEdit::singleline(text)
+                        .password(true)
+                        .desired_width(ui.available_width())
+                        .font(egui::FontId::new(
+                            *font_size,
+                            egui::FontFamily::Proportional,
+                        )),
+                    mark_as_required,
+                );
             }
             Field::List {
-                id,
+                id: _,
                 required,
-                header: _,
+                header,
                 selected,
-                values: _,
-                font_size: _,
+                values,
+                font_size,
+                conditions: _,
             } => {
-                if *required && selected.len() == 0 {
-                    close_window = false;
-                }
-                Some(ResponseBody {
-                    id: id.to_string(),
-                    value: selected.to_string(),
-                })
+                TableBuilder::new(ui)
+                    .striped(true)
+                    .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+                    .column(Column::remainder().at_least(100.0))
+                    .header(20.0, |mut row| {
+                        row.col(|ui| {
+                            let heading = if *required && selected.len() == 0 {
+                                egui::RichText::new(header.as_str())
+                                    .size(*font_size)
+                                    .color(egui::Color32::RED)
+                            } else {
+                                egui::RichText::new(header.as_str()).size(*font_size)
+                            };
+                            ui.heading(heading);
+                        });
+                    })
+                    .body(|mut body| {
+                        for v in values.iter() {
+                            body.row(18.0, |mut row| {
+                                row.col(|ui| {
+                                    ui.selectable_value(
+                                        selected,
+                                        v.to_string(),
+                                        egui::RichText::new(v.as_str()).size(*font_size),
+                                    );
+                                });
+                            });
+                        }
+                    });
             }
             Field::Color {
-                id,
-                required: _,
-                label: _,
-                label_pos: _,
-                font_size: _,
+                id: _,
+                required,
+                label,
+                label_pos,
+                font_size,
                 rgb,
-            } => Some(ResponseBody {
-                id: id.to_string(),
-                value: format!("[{},{},{}]", rgb[0], rgb[1], rgb[2]),
-            }),
+                touched,
+                conditions: _,
+            } => {
+                let label_w = if *required && !*touched {
+                    egui::RichText::new(label.as_str())
+                        .size(*font_size)
+                        .color(egui::Color32::RED)
+                } else {
+                    egui::RichText::new(label.as_str()).size(*font_size)
+                };
+                let response = match label_pos {
+                    LabelPos::Over => {
+                        ui.vertical(|ui| {
+                            ui.label(label_w);
+                            egui::widgets::color_picker::color_edit_button_srgb(ui, rgb)
+                        })
+                        .inner
+                    }
+                    LabelPos::Next => {
+                        ui.horizontal(|ui| {
+                            ui.label(label_w);
+                            egui::widgets::color_picker::color_edit_button_srgb(ui, rgb)
+                        })
+                        .inner
+                    }
+                };
+                if response.changed() {
+                    *touched = true;
+                }
+            }
             Field::Progress {
                 id: _,
-                label: _,
-                label_pos: _,
-                font_size: _,
-            } => None,
+                label,
+                label_pos,
+                font_size,
+                conditions: _,
+            } => {
+                match label_pos {
+                    LabelPos::Over => {
+                        ui.vertical(|ui| {
+                            ui.label(egui::RichText::new(label.as_str()).size(*font_size));
+                            ui.add(
+                                egui::ProgressBar::new(self.progress / 100.)
+                                    .show_percentage()
+                                    .desired_width(ui.available_width()),
+                            );
+                            // ui.add(egui::Spinner::new());
+                        });
+                    }
+                    LabelPos::Next => {
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new(label.as_str()).size(*font_size));
+                            ui.add(
+                                egui::ProgressBar::new(self.progress / 100.)
+                                    .show_percentage()
+                                    .desired_width(ui.available_width()),
+                            );
+                            ui.add(egui::Spinner::new());
+                        });
+                    }
+                }
+                ui.ctx().request_repaint();
+            }
             Field::Check {
-                id,
+                id: _,
                 required,
-                label: _,
-                label_pos: _,
-                font_size: _,
+                label,
+                label_pos,
+                font_size,
                 checked,
-            } => {
-                if *required && !checked {
-                    close_window = false;
+                conditions: _,
+            } => match label_pos {
+                HLabelPos::Before => {
+                    ui.horizontal(|ui| {
+                        let label_w = if *required && !*checked {
+                            egui::RichText::new(label.as_str())
+                                .size(*font_size)
+                                .color(egui::Color32::RED)
+                        } else {
+                            egui::RichText::new(label.as_str()).size(*font_size)
+                        };
+                        ui.label(label_w);
+                        ui.checkbox(checked, "");
+                    });
                 }
-                Some(ResponseBody {
-                    id: id.to_string(),
-                    value: checked.to_string(),
-                })
-            }
+                HLabelPos::After => {
+                    let label_w = if *required && !*checked {
+                        egui::RichText::new(label.as_str())
+                            .size(*font_size)
+                            .color(egui::Color32::RED)
+                    } else {
+                        egui::RichText::new(label.as_str()).size(*font_size)
+                    };
+                    ui.checkbox(checked, label_w);
+                }
+            },
             Field::Radio {
-                id,
+                id: _,
                 required,
-                label: _,
-                label_pos: _,
-                font_size: _,
+                label,
+                label_pos,
+                font_size,
                 selected,
-                options: _,
-            } => {
-                if *required && selected.len() == 0 {
-                    close_window = false;
+                options,
+                conditions: _,
+            } => match label_pos {
+                LabelPos::Over => {
+                    ui.vertical(|ui| {
+                        let label_w = if *required && selected.len() == 0 {
+                            egui::RichText::new(label.as_str())
+                                .size(*font_size)
+                                .color(egui::Color32::RED)
+                        } else {
+                            egui::RichText::new(label.as_str()).size(*font_size)
+                        };
+                        ui.label(label_w);
+                        for opt in options.iter() {
+                            ui.radio_value(
+                                selected,
+                                opt.to_string(),
+                                egui::RichText::new(opt.as_str()).size(*font_size),
+                            );
+                        }
+                    });
                 }
-                Some(ResponseBody {
-                    id: id.to_string(),
-                    value: selected.to_string(),
-                })
-            }
+                LabelPos::Next => {
+                    ui.horizontal(|ui| {
+                        if *required && selected.len() == 0 {
+                            ui.label(egui::RichText::new(label.as_str()).color(egui::Color32::RED));
+                        } else {
+                            ui.label(label.as_str());
+                        }
+                        for opt in options.iter() {
+                            ui.radio_value(
+                                selected,
+                                opt.to_string(),
+                                egui::RichText::new(opt.as_str()).size(*font_size),
+                            );
+                        }
+                    });
+                }
+            },
             Field::Slider {
-                id,
-                required: _,
-                label: _,
-                label_pos: _,
-                font_size: _,
-                min: _,
-                max: _,
+                id: _,
+                required,
+                label,
+                label_pos,
+                font_size,
+                min,
+                max,
                 value,
-                suffix: _,
-            } => Some(ResponseBody {
-                id: id.to_string(),
-                value: value.to_string(),
-            }),
+                suffix,
+                validation: _,
+                touched,
+                conditions: _,
+            } => {
+                let mark_as_required = *required && !*touched;
+                if GUI::add_labeled_widget(
+                    ui,
+                    &label,
+                    &label_pos,
+                    *font_size,
+                    egui::Slider::new(value, *min..=*max).suffix(suffix.as_str()),
+                    mark_as_required,
+                )
+                .changed()
+                {
+                    *touched = true;
+                }
+            }
             Field::Combobox {
-                id,
+                id: _,
                 required,
-                label: _,
-                font_size: _,
-                options: _,
+                label,
+                font_size,
+                options,
                 selected,
+                conditions: _,
             } => {
-                if *required && selected.len() == 0 {
-                    close_window = false;
-                }
-                Some(ResponseBody {
-                    id: id.to_string(),
-                    value: selected.to_string(),
-                })
+                let label_w = if *required && selected.len() == 0 {
+                    egui::RichText::new(label.as_str())
+                        .size(*font_size)
+                        .color(egui::Color32::RED)
+                } else {
+                    egui::RichText::new(label.as_str()).size(*font_size)
+                };
+                let combo = egui::ComboBox::from_label(label_w);
+                combo.selected_text(selected.as_str()).show_ui(ui, |ui| {
+                    for opt in options {
+                        ui.selectable_value(
+                            selected,
+                            opt.to_string(),
+                            egui::RichText::new(opt.as_str()).size(*font_size),
+                        );
+                    }
+                });
             }
             Field::Image {
-                id,
+                id: _,
                 path,
-                scale: _,
-                h_align: _,
-            } => Some(ResponseBody {
-                id: id.to_string(),
-                value: path.to_string(),
-            }),
-        })
-        .collect();
-    if close_window {
-        Response::ok(out);
+                scale,
+                h_align,
+                conditions: _,
+            } => {
+                ui.horizontal(|ui| {
+                    let img = egui::Image::new(path.to_string()).fit_to_original_size(*scale);
+                    let tpoll = match img.load_for_size(ui.ctx(), ui.available_size()) {
+                        Ok(tpoll) => tpoll,
+                        // Drawn in place of the image, the dialog can still be used
+                        Err(err) => {
+                            ui.colored_label(
+                                egui::Color32::RED,
+                                format!("Unable to load image {}: {}", path, err),
+                            );
+                            return;
+                        }
+                    };
+                    let mut img_w = 0.;
+
+                    match tpoll.size() {
+                        Some(s) => {
+                            img_w = s.x * *scale;
+                        }
+                        None => {}
+                    }
+                    let space = match h_align {
+                        HAlign::Left => 0.,
+                        HAlign::Center => (ui.available_width() - img_w) * 0.5,
+                        HAlign::Right => ui.available_width() - img_w,
+                    };
+
+                    ui.add_space(space);
+                    ui.add(img);
+                });
+            }
+            Field::Row {
+                id: _,
+                body,
+                conditions: _,
+            } => {
+                ui.horizontal(|ui| self.show_fields(ui, body));
+            }
+            Field::Column {
+                id: _,
+                body,
+                conditions: _,
+            } => {
+                ui.vertical(|ui| self.show_fields(ui, body));
+            }
+            Field::Group {
+                id: _,
+                title,
+                font_size,
+                body,
+                conditions: _,
+            } => {
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        if !title.is_empty() {
+                            ui.label(
+                                egui::RichText::new(title.as_str())
+                                    .size(*font_size)
+                                    .strong(),
+                            );
+                        }
+                        self.show_fields(ui, body);
+                    });
+                });
+            }
+            Field::Collapsing {
+                id: _,
+                title,
+                open,
+                font_size,
+                body,
+                conditions: _,
+            } => {
+                egui::CollapsingHeader::new(egui::RichText::new(title.as_str()).size(*font_size))
+                    .default_open(*open)
+                    .show(ui, |ui| self.show_fields(ui, body));
+            }
+            Field::Grid {
+                id: _,
+                columns,
+                striped,
+                body,
+                conditions: _,
+            } => {
+                let columns = (*columns).max(1);
+                egui::Grid::new("grid")
+                    .num_columns(columns)
+                    .striped(*striped)
+                    .show(ui, |ui| {
+                        let mut shown = 0;
+                        for (index, field) in body.iter_mut().enumerate() {
+                            if self.show_entry(ui, index, field) {
+                                shown += 1;
+                                if shown % columns == 0 {
+                                    ui.end_row();
+                                }
+                            }
+                        }
+                    });
+            }
+            Field::Tabs {
+                id: _,
+                tabs,
+                selected,
+                conditions: _,
+            } => {
+                ui.horizontal(|ui| {
+                    for (index, tab) in tabs.iter().enumerate() {
+                        let has_errors = self.show_errors
+                            && custom_dialog::visible_fields(&tab.body, self.values)
+                                .iter()
+                                .any(|field| field.validate().is_err());
+                        let title = if has_errors {
+                            egui::RichText::new(tab.title.as_str()).color(egui::Color32::RED)
+                        } else {
+                            egui::RichText::new(tab.title.as_str())
+                        };
+                        ui.selectable_value(selected, index, title);
+                    }
+                });
+                ui.separator();
+                if let Some(tab) = tabs.get_mut(*selected) {
+                    self.show_fields(ui, &mut tab.body);
+                }
+            }
+        }
+    }
+}
+
+fn confirm_close(gui: &mut GUI) -> bool {
+    // Closing the window is the same as pressing the escape button
+    let button = gui.pressed.take().or(gui.find_button(|b| b.escape));
+    let button_id = match &button {
+        Some(b) if gui.custom_buttons => Some(b.id.clone()),
+        _ => None,
+    };
+    let (result, validate) = match &button {
+        Some(b) => (b.result, b.validate),
+        None => (ButtonResult::Cancel, false),
+    };
+
+    if result == ButtonResult::Cancel {
+        Response::cancel_with_button(button_id, gui.wizard.then_some(gui.page));
+        return true;
+    }
+
+    let values = gui.values();
+
+    // Show the first page with errors, if any. Hidden fields and skipped pages are
+    // neither validated nor returned
+    let invalid = (0..gui.pages.len()).find(|page| {
+        validate && !gui.is_skipped(*page, &values) && !gui.page_is_valid(*page, &values)
+    });
+    if let Some(invalid) = invalid {
+        gui.page = invalid;
+        gui.show_errors = true;
+        return false;
     }
 
-    gui.ok_pressed = false;
+    Response::ok_with_button(gui.output(&values), button_id);
 
-    close_window
+    true
 }
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use regex::{Captures, Regex};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::custom_dialog::CustomDialog;
+
+/// Environment variable with a folder whose layouts replace the built-in ones
+pub const LAYOUTS_DIR_VAR: &str = "CLIALOGS_LAYOUTS_DIR";
+
+/// Layouts of the built-in commands, compiled into the binary so it doesn't need the
+/// `def_layouts` folder to work.
+const BUILT_IN: [(&str, &str); 7] = [
+    ("calendar", include_str!("../def_layouts/calendar.json")),
+    ("color", include_str!("../def_layouts/color.json")),
+    ("input", include_str!("../def_layouts/input.json")),
+    ("list", include_str!("../def_layouts/list.json")),
+    ("log_in", include_str!("../def_layouts/log_in.json")),
+    ("progress", include_str!("../def_layouts/progress.json")),
+    ("select", include_str!("../def_layouts/select.json")),
+];
+
+pub enum Layout {
+    /// Layout of a built-in command, by name
+    BuiltIn(&'static str),
+    /// Layout file given by the user, relative to the current folder or to the executable one
+    Path(String),
+    /// Layout read from stdin, given as `-`
+    Stdin,
+    /// Layout given in the command line
+    Inline(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum LayoutFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+/// Error parsing a layout, with the line and column where it was found, if known.
+pub struct ParseError {
+    pub message: String,
+    pub position: Option<(usize, usize)>,
+}
+
+impl Layout {
+    /// Layout given in the command line, where `-` means stdin
+    pub fn from_arg(path: String) -> Self {
+        if path == "-" {
+            Layout::Stdin
+        } else {
+            Layout::Path(path)
+        }
+    }
+}
+
+impl Layout {
+    /// Format given by the extension of the file. Anything else is JSON
+    pub fn format(&self) -> LayoutFormat {
+        let extension = match self {
+            Layout::Path(path) => Path::new(path).extension().and_then(|e| e.to_str()),
+            _ => None,
+        };
+        match extension.map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("yaml") | Some("yml") => LayoutFormat::Yaml,
+            Some("toml") => LayoutFormat::Toml,
+            _ => LayoutFormat::Json,
+        }
+    }
+
+    pub fn read(&self) -> Result<String, String> {
+        match self {
+            Layout::BuiltIn(name) => {
+                let file_name = format!("{}.json", name);
+                for dir in override_dirs() {
+                    let path = dir.join(&file_name);
+                    if !path.is_file() {
+                        continue;
+                    }
+                    let data = read_file(path.clone())?;
+                    if is_legacy_template(&data) {
+                        eprintln!(
+                            "Layout {} ignored, its placeholders must be quoted now",
+                            path.display()
+                        );
+                        continue;
+                    }
+                    return Ok(data);
+                }
+                BUILT_IN
+                    .iter()
+                    .find(|(built_in, _)| built_in == name)
+                    .map(|(_, data)| data.to_string())
+                    .ok_or(format!("Unknown built-in layout {}", name))
+            }
+            // The executable folder is kept for the layouts which were next to it
+            Layout::Path(path) if Path::new(path).exists() => read_file(PathBuf::from(path)),
+            Layout::Path(path) => read_file(exe_dir().join(path)),
+            Layout::Stdin => {
+                let mut data = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut data)
+                    .map(|_| data)
+                    .map_err(|e| format!("Unable to read the layout from stdin: {}", e))
+            }
+            Layout::Inline(data) => Ok(data.clone()),
+        }
+    }
+}
+
+/// Parses the layout in any format and replaces its placeholders with the variables.
+pub fn parse(
+    data: &str,
+    format: LayoutFormat,
+    vars: &HashMap<String, Value>,
+) -> Result<CustomDialog, ParseError> {
+    // Without variables the layout is parsed from the text, so the errors have their position
+    if vars.is_empty() {
+        return parse_as(data, format);
+    }
+    serde_json::from_value(parse_value(data, format, vars)?).map_err(|e| ParseError {
+        message: e.to_string(),
+        position: None,
+    })
+}
+
+/// Parses the layout as a JSON value with its placeholders replaced, without checking its fields.
+pub fn parse_value(
+    data: &str,
+    format: LayoutFormat,
+    vars: &HashMap<String, Value>,
+) -> Result<Value, ParseError> {
+    let mut value: Value = parse_as(data, format)?;
+    apply_vars(&mut value, vars);
+    Ok(value)
+}
+
+fn parse_as<T: DeserializeOwned>(data: &str, format: LayoutFormat) -> Result<T, ParseError> {
+    match format {
+        LayoutFormat::Json => serde_json::from_str(data).map_err(|e| ParseError {
+            message: e.to_string(),
+            position: (e.line() > 0).then_some((e.line(), e.column())),
+        }),
+        // The fields are maps with a single key, like in JSON, instead of YAML tags
+        LayoutFormat::Yaml => serde_yaml::with::singleton_map_recursive::deserialize(
+            serde_yaml::Deserializer::from_str(data),
+        )
+        .map_err(|e| ParseError {
+            message: e.to_string(),
+            position: e.location().map(|l| (l.line(), l.column())),
+        }),
+        LayoutFormat::Toml => toml::from_str(data).map_err(|e| ParseError {
+            message: e.message().to_string(),
+            position: e.span().map(|span| line_column(data, span.start)),
+        }),
+    }
+}
+
+/// Line and column (starting at 1) of the byte offset in the text
+fn line_column(data: &str, offset: usize) -> (usize, usize) {
+    let before = &data[..offset.min(data.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, offset - line_start + 1)
+}
+
+/// Replaces the `__key__` placeholders in the strings of the layout with the given variables.
+/// A string which is only a placeholder is replaced by the value as it is (so it can be an
+/// array or a number), and the placeholders inside longer strings are replaced by the text of
+/// the value. Nothing is parsed again, so the values don't need any escaping.
+pub fn apply_vars(layout: &mut Value, vars: &HashMap<String, Value>) {
+    let placeholder = Regex::new(r"__([\w-]+?)__").unwrap();
+    apply_vars_with(layout, vars, &placeholder);
+}
+
+fn apply_vars_with(layout: &mut Value, vars: &HashMap<String, Value>, placeholder: &Regex) {
+    match layout {
+        Value::String(text) => {
+            let whole = placeholder
+                .captures(text)
+                .filter(|captures| captures[0].len() == text.len())
+                .and_then(|captures| vars.get(&captures[1]));
+            if let Some(value) = whole {
+                *layout = value.clone();
+                return;
+            }
+            let replaced = placeholder.replace_all(text, |captures: &Captures| {
+                match vars.get(&captures[1]) {
+                    Some(Value::String(value)) => value.clone(),
+                    Some(value) => value.to_string(),
+                    // Unknown placeholders are left untouched
+                    None => captures[0].to_string(),
+                }
+            });
+            *text = replaced.into_owned();
+        }
+        Value::Array(values) => {
+            for value in values.iter_mut() {
+                apply_vars_with(value, vars, placeholder);
+            }
+        }
+        Value::Object(map) => {
+            for value in map.values_mut() {
+                apply_vars_with(value, vars, placeholder);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses `key=value` variables from the command line.
+pub fn parse_var(var: &str) -> Result<(String, String), String> {
+    match var.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected key=value, found '{}'", var)),
+    }
+}
+
+/// Folders where the built-in layouts can be replaced by files with the same name, in order:
+/// `$CLIALOGS_LAYOUTS_DIR`, `<config dir>/clialogs/layouts` and `def_layouts` next to the
+/// executable, where the old releases left their own layouts.
+pub fn override_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = env::var_os(LAYOUTS_DIR_VAR) {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(config) = dirs::config_dir() {
+        dirs.push(config.join("clialogs").join("layouts"));
+    }
+    dirs.push(exe_dir().join("def_layouts"));
+    dirs
+}
+
+/// Layouts of the older versions had placeholders out of the strings (`"values": __values__`),
+/// which are not valid JSON.
+fn is_legacy_template(data: &str) -> bool {
+    Regex::new(r"[:\[,]\s*__[\w-]+__\s*[,\]}]")
+        .unwrap()
+        .is_match(data)
+}
+
+fn exe_dir() -> PathBuf {
+    let exe = env::current_exe().unwrap_or_default();
+    exe.parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or(PathBuf::from("."))
+}
+
+fn read_file(path: PathBuf) -> Result<String, String> {
+    fs::read_to_string(&path).map_err(|e| format!("Unable to read file {}: {}", path.display(), e))
+}
@@ -1,4 +1,8 @@
+pub mod check;
 pub mod cli;
+pub mod condition;
 pub mod custom_dialog;
 pub mod gui;
+pub mod layout;
 pub mod response;
+pub mod validation;
@@ -1,31 +1,113 @@
 use clap::Parser;
 use clialogs::{
-    cli::{Command, MessageDialogLevel},
-    response::{Response, ResponseBody},
+    check,
+    cli::{Command, MessageDialogLevel, NotificationUrgency},
+    custom_dialog::CustomDialog,
+    layout::{self, Layout, LayoutFormat},
+    response::{
+        check_fd, ErrorCode, Output, Response, ResponseBody, SavedResponse, SavedValue, Sink,
+    },
 };
 use egui::IconData;
 use image::GenericImageView;
-use regex::Regex;
+use notify_rust::{Hint, Urgency};
 use rfd::{FileDialog, MessageButtons, MessageDialog, MessageLevel};
+use serde_json::Value;
 use std::{collections::HashMap, fs, path::Path, vec};
 
 fn main() {
     let cli = clialogs::cli::Cli::parse();
+    if let Some(fd) = cli.output_fd {
+        if let Err(err) = check_fd(fd) {
+            Response::error(
+                ErrorCode::InvalidArgument,
+                format!("Invalid --output-fd {}: {}", fd, err),
+                None,
+            );
+        }
+    }
+    Output {
+        version: cli.response_version,
+        format: cli.output_format,
+        sink: match (cli.output_file, cli.output_fd) {
+            (Some(path), _) => Sink::File(path),
+            (None, Some(fd)) => Sink::Fd(fd),
+            (None, None) => Sink::Stdout,
+        },
+    }
+    .init();
     let arg_icon_path = cli.icon_path;
-    let (path, patterns): (String, HashMap<&str, String>) = match cli.command {
-        Command::Notification { title, text } => {
+    let arg_events = cli.events;
+    let arg_buttons = cli.buttons;
+    let arg_timeout = cli.timeout;
+    let arg_timeout_action = cli.timeout_action;
+    let arg_countdown = cli.countdown;
+    let arg_defaults = cli.defaults;
+    let mut layout_format = None;
+    let (layout, vars): (Layout, HashMap<String, Value>) = match cli.command {
+        Command::Notification {
+            title,
+            text,
+            actions,
+            wait,
+            urgency,
+            expire_timeout,
+            category,
+            app_name,
+            sound_name,
+            transient,
+            resident,
+            mut hints,
+            replace_id,
+            progress,
+        } => {
             let mut not = notify_rust::Notification::new();
             not.summary(&title);
             not.body(&text);
             if let Some(icon) = arg_icon_path {
                 not.icon(&icon);
             }
-            match not.show() {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("Error showing notification {}", err);
-                }
-            };
+            if let Some(app_name) = app_name {
+                not.appname(&app_name);
+            }
+            if let Some(expire_timeout) = expire_timeout {
+                not.timeout(expire_timeout);
+            }
+            if let Some(sound_name) = sound_name {
+                not.sound_name(&sound_name);
+            }
+            if let Some(replace_id) = replace_id {
+                not.id(replace_id);
+            }
+            for (id, label) in actions.iter() {
+                not.action(id, label);
+            }
+            if let Some(urgency) = urgency {
+                hints.push(Hint::Urgency(match urgency {
+                    NotificationUrgency::Low => Urgency::Low,
+                    NotificationUrgency::Normal => Urgency::Normal,
+                    NotificationUrgency::Critical => Urgency::Critical,
+                }));
+            }
+            if let Some(category) = category {
+                hints.push(Hint::Category(category));
+            }
+            if transient {
+                hints.push(Hint::Transient(true));
+            }
+            if resident {
+                hints.push(Hint::Resident(true));
+            }
+            watch_timeout(arg_timeout);
+            if progress {
+                show_notification_progress(not, hints);
+            } else {
+                show_notification(not, hints, wait);
+            }
+            return;
+        }
+        Command::NotificationClose { id } => {
+            close_notification(id);
             return;
         }
         Command::FileDialog {
@@ -33,12 +115,32 @@ fn main() {
             open_directory,
             multiple,
             save,
+            title,
+            file_name,
+            filters,
         } => {
-            let dialog = if open_directory != "" {
-                FileDialog::new().set_directory(open_directory)
-            } else {
-                FileDialog::new()
-            };
+            watch_timeout(arg_timeout);
+            let mut dialog = FileDialog::new();
+            if open_directory != "" {
+                let path = Path::new(&open_directory);
+                // Starting in a file selects it in its directory
+                dialog = match (path.is_file(), path.parent(), path.file_name()) {
+                    (true, Some(parent), Some(name)) => dialog
+                        .set_directory(parent)
+                        .set_file_name(&name.to_string_lossy()),
+                    _ => dialog.set_directory(path),
+                };
+            }
+            if let Some(title) = title {
+                dialog = dialog.set_title(&title);
+            }
+            if let Some(file_name) = file_name {
+                dialog = dialog.set_file_name(&file_name);
+            }
+            for (name, extensions) in filters.iter() {
+                let extensions: Vec<&str> = extensions.iter().map(String::as_str).collect();
+                dialog = dialog.add_filter(name, &extensions);
+            }
 
             let opt_paths = if save {
                 match dialog.save_file() {
@@ -66,16 +168,22 @@ fn main() {
             };
 
             if let Some(paths) = opt_paths {
-                Response::ok(vec![ResponseBody {
-                    id: "paths".to_string(),
-                    value: format!("{:?}", paths),
-                }]);
+                let typed_paths = paths
+                    .iter()
+                    .map(|path| serde_json::Value::String(path.display().to_string()))
+                    .collect();
+                Response::ok(vec![ResponseBody::new(
+                    "paths".to_string(),
+                    format!("{:?}", paths),
+                    serde_json::Value::Array(typed_paths),
+                )]);
             } else {
                 Response::cancel();
             }
             return;
         }
         Command::MessageDialog { title, level, text } => {
+            watch_timeout(arg_timeout);
             let buttons = match level {
                 MessageDialogLevel::Question => MessageButtons::OkCancel,
                 _ => MessageButtons::Ok,
@@ -99,15 +207,18 @@ fn main() {
             return;
         }
         Command::Progress { title, label } => (
-            "def_layouts/progress.json".to_string(),
-            HashMap::from([("title", title), ("label", label)]),
+            Layout::BuiltIn("progress"),
+            HashMap::from([
+                ("title".to_string(), title.into()),
+                ("label".to_string(), label.into()),
+            ]),
         ),
         Command::Input { title, label, hint } => (
-            "def_layouts/input.json".to_string(),
+            Layout::BuiltIn("input"),
             HashMap::from([
-                ("title", title),
-                ("label", label),
-                ("placeholder", hint.unwrap_or("".to_string())),
+                ("title".to_string(), title.into()),
+                ("label".to_string(), label.into()),
+                ("placeholder".to_string(), hint.unwrap_or_default().into()),
             ]),
         ),
         Command::LogIn {
@@ -116,12 +227,12 @@ fn main() {
             user_label,
             pass_label,
         } => (
-            "def_layouts/log_in.json".to_string(),
+            Layout::BuiltIn("log_in"),
             HashMap::from([
-                ("title", title),
-                ("label", label),
-                ("user_label", user_label),
-                ("pass_label", pass_label),
+                ("title".to_string(), title.into()),
+                ("label".to_string(), label.into()),
+                ("user_label".to_string(), user_label.into()),
+                ("pass_label".to_string(), pass_label.into()),
             ]),
         ),
         Command::Calendar {
@@ -129,27 +240,30 @@ fn main() {
             label,
             date_format,
         } => (
-            "def_layouts/calendar.json".to_string(),
+            Layout::BuiltIn("calendar"),
             HashMap::from([
-                ("title", title),
-                ("label", label),
-                ("date-format", date_format),
+                ("title".to_string(), title.into()),
+                ("label".to_string(), label.into()),
+                ("date-format".to_string(), date_format.into()),
             ]),
         ),
         Command::Color { title, label } => (
-            "def_layouts/color.json".to_string(),
-            HashMap::from([("title", title), ("label", label)]),
+            Layout::BuiltIn("color"),
+            HashMap::from([
+                ("title".to_string(), title.into()),
+                ("label".to_string(), label.into()),
+            ]),
         ),
         Command::List {
             title,
             header,
             values,
         } => (
-            "def_layouts/list.json".to_string(),
+            Layout::BuiltIn("list"),
             HashMap::from([
-                ("title", title),
-                ("header", header),
-                ("values", format!("{:?}", values)),
+                ("title".to_string(), title.into()),
+                ("header".to_string(), header.into()),
+                ("values".to_string(), values.into()),
             ]),
         ),
         Command::Select {
@@ -157,40 +271,93 @@ fn main() {
             label,
             options,
         } => (
-            "def_layouts/select.json".to_string(),
+            Layout::BuiltIn("select"),
             HashMap::from([
-                ("title", title),
-                ("label", label),
-                ("options", format!("{:?}", options)),
+                ("title".to_string(), title.into()),
+                ("label".to_string(), label.into()),
+                ("options".to_string(), options.into()),
             ]),
         ),
-        Command::Custom { layout_path } => (layout_path, HashMap::new()),
+        Command::Custom {
+            layout_path,
+            layout_json,
+            layout_format: format,
+            vars,
+        } => {
+            layout_format = format;
+            (
+                match layout_json {
+                    Some(data) => Layout::Inline(data),
+                    None => Layout::from_arg(layout_path.unwrap_or_default()),
+                },
+                string_vars(vars),
+            )
+        }
+        Command::Validate {
+            layout,
+            layout_format,
+            vars,
+        } => {
+            validate(Layout::from_arg(layout), layout_format, string_vars(vars));
+            return;
+        }
+        Command::Schema => {
+            let schema = schemars::schema_for!(CustomDialog);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema).unwrap_or_default()
+            );
+            return;
+        }
     };
 
-    if path.len() == 0 {
-        return;
-    }
-
-    let path_buf = std::env::current_exe().unwrap_or(std::path::PathBuf::new());
-    let parent = path_buf.parent().unwrap_or(Path::new("."));
-    let mut path_buf = parent.to_path_buf();
-    path_buf.push(path);
-
-    let mut data = fs::read_to_string(path_buf.as_path()).expect("Unable to read file");
+    let data = match layout.read() {
+        Ok(data) => data,
+        Err(err) => Response::error(ErrorCode::LayoutRead, err, None),
+    };
 
-    for (key, value) in patterns {
-        match replace(format!("__{}__", key), value, &data.as_str()[..]) {
-            Ok(d) => data = d,
-            Err(err) => {
-                println!("Error replacing {}", err)
+    let format = layout_format.unwrap_or(layout.format());
+    let mut custom_dialog_data = match layout::parse(&data, format, &vars) {
+        Ok(custom_dialog_data) => custom_dialog_data,
+        Err(err) => {
+            if let Some((line, column)) = err.position {
+                print_error_line(&data, line, column);
             }
+            Response::error(
+                ErrorCode::LayoutParse,
+                format!("Unable to parse: {}", err.message),
+                err.position,
+            )
+        }
+    };
+    let mut problems = check::check_dialog(&custom_dialog_data);
+    problems.extend(check::check_images(&custom_dialog_data));
+    report_problems(&problems);
+    if let Some(path) = arg_defaults {
+        let values = read_defaults(&path)
+            .into_iter()
+            .map(|saved| (saved.id, saved.value))
+            .collect();
+        for error in custom_dialog_data.set_values(values) {
+            eprintln!("Default value not set {}", error);
         }
     }
+    custom_dialog_data.events |= arg_events;
+    if !arg_buttons.is_empty() {
+        custom_dialog_data.buttons = arg_buttons;
+    }
+    if arg_timeout.is_some() {
+        custom_dialog_data.timeout = arg_timeout;
+    }
+    if let Some(timeout_action) = arg_timeout_action {
+        custom_dialog_data.timeout_action = timeout_action;
+    }
+    custom_dialog_data.countdown |= arg_countdown;
 
-    let custom_dialog_data: clialogs::custom_dialog::CustomDialog =
-        serde_json::from_str(&data).expect("Unable to parse");
-
-    let title = custom_dialog_data.title.unwrap_or("Title".to_string());
+    let title = custom_dialog_data
+        .title
+        .clone()
+        .unwrap_or("Title".to_string());
     let mut native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_position(egui::Pos2 {
@@ -210,16 +377,22 @@ fn main() {
         ..Default::default()
     };
 
-    let icon_path = match custom_dialog_data.icon_path {
-        Some(d) => Some(d),
+    // A wrong icon in the layout is a layout error, a wrong --icon-path is a usage error
+    let icon_path = match custom_dialog_data.icon_path.clone() {
+        Some(d) => Some((d, ErrorCode::InvalidIcon)),
         None => match arg_icon_path {
-            Some(d) => Some(d),
+            Some(d) => Some((d, ErrorCode::InvalidArgument)),
             None => None,
         },
     };
 
-    if let Some(icon) = icon_path {
-        let img = image::open(icon).unwrap();
+    if let Some((icon, code)) = icon_path {
+        let img = match image::open(&icon) {
+            Ok(img) => img,
+            Err(err) => {
+                Response::error(code, format!("Unable to open icon {}: {}", icon, err), None)
+            }
+        };
         let (width, height) = img.dimensions();
 
         native_options.viewport.icon = Some(std::sync::Arc::new(IconData {
@@ -229,17 +402,316 @@ fn main() {
         }));
     }
 
-    let _ = eframe::run_native(
+    if let Err(err) = eframe::run_native(
         &title.as_str(),
         native_options,
         Box::new(|cc| {
             egui_extras::install_image_loaders(&cc.egui_ctx);
-            Ok(Box::new(clialogs::gui::GUI::new(custom_dialog_data.body)))
+            Ok(Box::new(clialogs::gui::GUI::new(
+                custom_dialog_data,
+                &cc.egui_ctx,
+            )))
         }),
+    ) {
+        Response::error(
+            ErrorCode::Display,
+            format!("Error showing the dialog {}", err),
+            None,
+        );
+    }
+}
+
+/// Shows the notification and prints its id or, with `wait`, the id of the clicked action or the
+/// reason why the notification was closed.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show_notification(mut not: notify_rust::Notification, hints: Vec<Hint>, wait: bool) {
+    use notify_rust::{ActionResponse, CloseReason};
+
+    for hint in hints {
+        not.hint(hint);
+    }
+    let handle = match not.show() {
+        Ok(handle) => handle,
+        Err(err) => Response::error(
+            ErrorCode::Notification,
+            format!("Error showing notification {}", err),
+            None,
+        ),
+    };
+    let id = ResponseBody::new(
+        "id".to_string(),
+        handle.id().to_string(),
+        Value::from(handle.id()),
+    );
+    if !wait {
+        Response::ok(vec![id]);
+        return;
+    }
+    // The handle keeps its connection open while waiting, some servers drop the actions otherwise
+    notify_rust::handle_action(handle.id(), |action| match action {
+        ActionResponse::Custom(action) => Response::ok(vec![
+            id,
+            ResponseBody::new(
+                "action".to_string(),
+                action.to_string(),
+                Value::String(action.to_string()),
+            ),
+        ]),
+        ActionResponse::Closed(reason) => Response::closed(match reason {
+            CloseReason::Expired => "expired",
+            CloseReason::Dismissed => "dismissed",
+            CloseReason::CloseAction => "closed",
+            CloseReason::Other(_) => "other",
+        }),
+    });
+    drop(handle);
+    Response::error(
+        ErrorCode::Notification,
+        "Lost the connection with the notification server".to_string(),
+        None,
+    );
+}
+
+/// Hints are only supported by the D-Bus notification servers
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn show_notification(not: notify_rust::Notification, _hints: Vec<Hint>, wait: bool) {
+    if wait {
+        Response::error(
+            ErrorCode::InvalidArgument,
+            "--wait is only supported with D-Bus notification servers".to_string(),
+            None,
+        );
+    }
+    if let Err(err) = not.show() {
+        Response::error(
+            ErrorCode::Notification,
+            format!("Error showing notification {}", err),
+            None,
+        );
+    }
+    Response::ok(Vec::new());
+}
+
+/// Shows the notification with a progress bar (the `value` hint), updated with the commands read
+/// from stdin. It is closed when the progress reaches 100, with a close command or when stdin is
+/// closed.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show_notification_progress(mut not: notify_rust::Notification, hints: Vec<Hint>) {
+    use clialogs::gui::{read_user_input, CloseResult, UserInput};
+    use clialogs::response::Ack;
+
+    // Same id as the field of the progress window, so both take the same commands
+    const PROGRESS_ID: &str = "progress";
+
+    for hint in hints {
+        not.hint(hint);
+    }
+    not.hint(Hint::CustomInt("value".to_string(), 0));
+    let mut handle = match not.show() {
+        Ok(handle) => handle,
+        Err(err) => Response::error(
+            ErrorCode::Notification,
+            format!("Error showing notification {}", err),
+            None,
+        ),
+    };
+    let mut result = None;
+    read_user_input(|input| {
+        let progress = match input {
+            UserInput::Progress { value } => value,
+            UserInput::Set { id, .. } if id != PROGRESS_ID => {
+                Ack::error("set", Some(id.clone()), format!("unknown id '{}'", id));
+                return true;
+            }
+            UserInput::Set { id, value } => match value.as_f64() {
+                Some(value) => {
+                    Ack::result("set", Some(id), Ok(()));
+                    value as f32
+                }
+                None => {
+                    Ack::error("set", Some(id), format!("invalid progress '{}'", value));
+                    return true;
+                }
+            },
+            UserInput::SetLabel { id, .. } if id != PROGRESS_ID => {
+                Ack::error(
+                    "set_label",
+                    Some(id.clone()),
+                    format!("unknown id '{}'", id),
+                );
+                return true;
+            }
+            UserInput::SetLabel { id, label } => {
+                handle.body(&label);
+                handle.update();
+                Ack::result("set_label", Some(id), Ok(()));
+                return true;
+            }
+            UserInput::Close { result: close } => {
+                Ack::result("close", None, Ok(()));
+                result = Some(close);
+                return false;
+            }
+        };
+        handle.hint(Hint::CustomInt(
+            "value".to_string(),
+            progress.clamp(0., 100.) as i32,
+        ));
+        handle.update();
+        if progress >= 100. {
+            result = Some(CloseResult::Ok);
+            return false;
+        }
+        true
+    });
+    let id = handle.id();
+    handle.close();
+    match result {
+        Some(CloseResult::Ok) => Response::ok(vec![ResponseBody::new(
+            "id".to_string(),
+            id.to_string(),
+            Value::from(id),
+        )]),
+        _ => Response::cancel(),
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn show_notification_progress(_not: notify_rust::Notification, _hints: Vec<Hint>) {
+    Response::error(
+        ErrorCode::InvalidArgument,
+        "--progress is only supported with D-Bus notification servers".to_string(),
+        None,
+    );
+}
+
+/// Closes a notification shown before, by the id given by the server.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn close_notification(id: u32) {
+    let result = zbus::blocking::Connection::session().and_then(|connection| {
+        connection.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "CloseNotification",
+            &id,
+        )
+    });
+    match result {
+        Ok(_) => Response::ok(Vec::new()),
+        Err(err) => Response::error(
+            ErrorCode::Notification,
+            format!("Error closing notification {}", err),
+            None,
+        ),
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn close_notification(_id: u32) {
+    Response::error(
+        ErrorCode::InvalidArgument,
+        "notification-close is only supported with D-Bus notification servers".to_string(),
+        None,
     );
 }
 
-fn replace(target: String, replace_with: String, text: &str) -> Result<String, regex::Error> {
-    let regex = Regex::new(target.as_str())?;
-    Ok(regex.replace_all(text, replace_with.as_str()).to_string())
+/// The native dialogs and the notifications block this thread, so the timeout is watched from
+/// another one
+fn watch_timeout(timeout: Option<u64>) {
+    if let Some(secs) = timeout {
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(secs));
+            Response::timeout();
+        });
+    }
+}
+
+/// A missing file is not an error, so the same command can be used before the first response.
+/// The response is the last line, the file may have the events before it.
+fn read_defaults(path: &Path) -> Vec<SavedValue> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("Defaults file {} not found", path.display());
+            return Vec::new();
+        }
+        Err(err) => Response::error(
+            ErrorCode::InvalidArgument,
+            format!("Unable to read file {}: {}", path.display(), err),
+            None,
+        ),
+    };
+    let last = data.lines().rev().find(|line| !line.trim().is_empty());
+    match serde_json::from_str::<SavedResponse>(last.unwrap_or_default()) {
+        // The body of a cancel or an error response has no answers
+        Ok(saved) if saved.response_type != "ok" => {
+            eprintln!(
+                "Defaults file {} has a '{}' response, it is ignored",
+                path.display(),
+                saved.response_type
+            );
+            Vec::new()
+        }
+        Ok(saved) => saved.body,
+        Err(err) => Response::error(
+            ErrorCode::InvalidArgument,
+            format!("Unable to parse the defaults {}: {}", path.display(), err),
+            None,
+        ),
+    }
+}
+
+fn string_vars(vars: Vec<(String, String)>) -> HashMap<String, Value> {
+    vars.into_iter()
+        .map(|(key, value)| (key, Value::String(value)))
+        .collect()
+}
+
+/// Prints every problem of the layout, and exits with an error if any of them is not a warning
+fn validate(layout: Layout, format: Option<LayoutFormat>, vars: HashMap<String, Value>) {
+    let data = match layout.read() {
+        Ok(data) => data,
+        Err(err) => Response::error(ErrorCode::LayoutRead, err, None),
+    };
+    let format = format.unwrap_or(layout.format());
+    let value = match layout::parse_value(&data, format, &vars) {
+        Ok(value) => value,
+        Err(err) => {
+            if let Some((line, column)) = err.position {
+                print_error_line(&data, line, column);
+            }
+            Response::error(
+                ErrorCode::LayoutParse,
+                format!("Unable to parse: {}", err.message),
+                err.position,
+            )
+        }
+    };
+    report_problems(&check::check_layout(&value));
+    Response::ok(Vec::new());
+}
+
+/// Shows the warnings in stderr, and the errors (if any) in an `invalid_layout` response
+fn report_problems(problems: &[check::Problem]) {
+    // The errors are printed with the response
+    for problem in problems.iter().filter(|problem| problem.warning) {
+        eprintln!("{}", problem);
+    }
+    let errors: Vec<String> = problems
+        .iter()
+        .filter(|problem| !problem.warning)
+        .map(|problem| problem.to_string())
+        .collect();
+    if !errors.is_empty() {
+        Response::error(ErrorCode::InvalidLayout, errors.join("\n"), None);
+    }
+}
+
+/// Shows in stderr the line of the layout where the parse error is
+fn print_error_line(data: &str, line: usize, column: usize) {
+    if let Some(text) = line.checked_sub(1).and_then(|l| data.lines().nth(l)) {
+        eprintln!("{:>5} | {}", line, text);
+        eprintln!("{:>5} | {}^", "", " ".repeat(column.saturating_sub(1)));
+    }
 }
@@ -1,46 +1,508 @@
-use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{SecondsFormat, Utc};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+/// Everything written to the output file, which is rewritten with every new record
+static RECORDS: Mutex<String> = Mutex::new(String::new());
+
+/// How the responses are printed, given in the command line.
+#[derive(Debug)]
+pub struct Output {
+    pub version: ResponseVersion,
+    pub format: OutputFormat,
+    pub sink: Sink,
+}
+
+/// Where the final response and the events are written. The acks are always written in stdout.
+#[derive(Debug)]
+pub enum Sink {
+    Stdout,
+    /// The file is replaced atomically, so it is never read half-written
+    File(PathBuf),
+    /// Already open file descriptor, inherited from the parent process
+    Fd(i32),
+}
+
+impl Output {
+    /// Sets the output for the rest of the process. Only the first call has effect.
+    pub fn init(self) {
+        let _ = OUTPUT.set(self);
+    }
+}
+
+fn output() -> &'static Output {
+    OUTPUT.get_or_init(|| Output {
+        version: ResponseVersion::V1,
+        format: OutputFormat::Json,
+        sink: Sink::Stdout,
+    })
+}
+
+fn write_output(text: &str) {
+    let result = match &output().sink {
+        Sink::Stdout => {
+            print!("{}", text);
+            std::io::stdout().flush()
+        }
+        Sink::File(path) => {
+            let mut records = RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+            records.push_str(text);
+            write_atomically(path, &records)
+        }
+        Sink::Fd(fd) => write_fd(*fd, text),
+    };
+    if let Err(e) = result {
+        eprintln!("Error writing the output {}", e);
+    }
+}
+
+/// Writes a temporary file next to the target and renames it, so readers see all or nothing
+fn write_atomically(path: &PathBuf, text: &str) -> std::io::Result<()> {
+    let mut temp = path.clone().into_os_string();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    let mut file = File::create(&temp)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+/// Checks that the descriptor given in the command line is open for writing. It must be called
+/// before the process opens any file, so the number can't belong to a file opened by clialogs.
+#[cfg(unix)]
+pub fn check_fd(fd: i32) -> Result<(), String> {
+    // F_GETFL only reads the flags, it is safe for any number
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    match flags & libc::O_ACCMODE {
+        libc::O_WRONLY | libc::O_RDWR => Ok(()),
+        _ => Err("it is not open for writing".to_string()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn check_fd(_fd: i32) -> Result<(), String> {
+    Err("file descriptors are only supported on unix".to_string())
+}
+
+#[cfg(unix)]
+fn write_fd(fd: i32, text: &str) -> std::io::Result<()> {
+    use std::os::fd::FromRawFd;
+    // The descriptor is owned by the parent process, so it must not be closed. It was checked by
+    // check_fd at startup
+    let mut file = std::mem::ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    // A single write for the whole record, so it is not mixed with other writers
+    file.write_all(text.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_fd(_fd: i32, _text: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "file descriptors are only supported on unix",
+    ))
+}
+
+/// Format of the values in the responses and events.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ResponseVersion {
+    /// Every value is a string
+    #[value(name = "1")]
+    V1,
+    /// Values are native JSON: booleans, numbers, arrays, objects...
+    #[value(name = "2")]
+    V2,
+}
+
+/// Format of the final response. Acks and events are always printed as JSON.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    /// `export ID='value'` lines, to be used with `eval`
+    Shell,
+    /// `id=value` lines
+    Kv,
+    /// Only the values, one per line
+    Plain,
+    /// Only the values, each one ended by a NUL character, to be used with `xargs -0`
+    Nul,
+}
+
+/// Exit codes of the process, so scripts can check the result without parsing the response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitCode {
+    Ok = 0,
+    Cancel = 1,
+    /// Wrong arguments (also used by clap)
+    Usage = 2,
+    /// The layout can't be read or parsed
+    Layout = 3,
+    /// The window or the notification can't be shown
+    Display = 4,
+    Timeout = 5,
+}
+
+impl ExitCode {
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
 
 #[derive(Serialize)]
 pub struct Response {
+    /// Only printed for versions greater than 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u8>,
     #[serde(rename = "type")]
     response_type: ResponseType,
     body: Vec<ResponseBody>,
+    /// Page of the wizard where the dialog was cancelled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<usize>,
+    /// Id of the button which closed the dialog
+    #[serde(skip_serializing_if = "Option::is_none")]
+    button: Option<String>,
+    /// Why the notification was closed without clicking any of its actions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Error>,
+}
+
+/// Reason why the dialog couldn't be shown, printed instead of the values.
+#[derive(Serialize)]
+pub struct Error {
+    code: ErrorCode,
+    message: String,
+    /// Position of the error in the layout file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidArgument,
+    LayoutRead,
+    LayoutParse,
+    /// The layout could be parsed, but its fields are inconsistent
+    InvalidLayout,
+    InvalidIcon,
+    Display,
+    Notification,
+}
+
+impl ErrorCode {
+    pub fn exit_code(self) -> ExitCode {
+        match self {
+            ErrorCode::InvalidArgument => ExitCode::Usage,
+            ErrorCode::LayoutRead
+            | ErrorCode::LayoutParse
+            | ErrorCode::InvalidLayout
+            | ErrorCode::InvalidIcon => ExitCode::Layout,
+            ErrorCode::Display | ErrorCode::Notification => ExitCode::Display,
+        }
+    }
 }
 
 impl Response {
     pub fn ok(body: Vec<ResponseBody>) {
+        Response::ok_with_button(body, None);
+    }
+
+    pub fn ok_with_button(body: Vec<ResponseBody>, button: Option<String>) {
         Response {
+            version: None,
             response_type: ResponseType::Ok,
             body: body,
+            page: None,
+            button,
+            reason: None,
+            error: None,
         }
         .print();
     }
 
     pub fn cancel() {
+        Response::cancel_with_button(None, None);
+    }
+
+    pub fn cancel_with_button(button: Option<String>, page: Option<usize>) {
         Response {
+            version: None,
             response_type: ResponseType::Cancel,
             body: Vec::new(),
+            page,
+            button,
+            reason: None,
+            error: None,
         }
         .print();
     }
 
+    /// The notification was closed without clicking any of its actions
+    pub fn closed(reason: &str) {
+        Response {
+            version: None,
+            response_type: ResponseType::Cancel,
+            body: Vec::new(),
+            page: None,
+            button: None,
+            reason: Some(reason.to_string()),
+            error: None,
+        }
+        .print();
+    }
+
+    /// The dialog was closed because the timeout expired
+    pub fn timeout() {
+        Response {
+            version: None,
+            response_type: ResponseType::Timeout,
+            body: Vec::new(),
+            page: None,
+            button: None,
+            reason: None,
+            error: None,
+        }
+        .print();
+    }
+
+    /// Prints the error response, and the message in stderr, then exits with the code of the error.
+    pub fn error(code: ErrorCode, message: String, position: Option<(usize, usize)>) -> ! {
+        eprintln!("{}", message);
+        Response {
+            version: None,
+            response_type: ResponseType::Error,
+            body: Vec::new(),
+            page: None,
+            button: None,
+            reason: None,
+            error: Some(Error {
+                code,
+                message,
+                line: position.map(|p| p.0),
+                column: position.map(|p| p.1),
+            }),
+        }
+        .print();
+    }
+
+    fn print(mut self) -> ! {
+        if output().version == ResponseVersion::V2 {
+            self.version = Some(2);
+        }
+        match output().format {
+            OutputFormat::Json => match serde_json::to_string(&self) {
+                Ok(s) => write_output(&format!("{}\n", s)),
+                Err(e) => {
+                    eprintln!("Error serializing ResponseBodys {}", e)
+                }
+            },
+            format => write_output(&self.to_text(format)),
+        }
+        match (&self.response_type, &self.error) {
+            (ResponseType::Ok, _) => ExitCode::Ok,
+            (ResponseType::Timeout, _) => ExitCode::Timeout,
+            (_, Some(error)) => error.code.exit_code(),
+            _ => ExitCode::Cancel,
+        }
+        .exit()
+    }
+
+    /// Only the values are printed in the text formats, the result is given by the exit code
+    fn to_text(&self, format: OutputFormat) -> String {
+        let mut text = String::new();
+        for body in self.body.iter() {
+            let values = body.text_values();
+            match format {
+                OutputFormat::Shell => text.push_str(&format!(
+                    "export {}={}\n",
+                    shell_name(&body.id),
+                    shell_quote(&values.join("\n"))
+                )),
+                OutputFormat::Kv => {
+                    for value in values {
+                        let value = value.replace('\\', "\\\\").replace('\n', "\\n");
+                        text.push_str(&format!("{}={}\n", body.id, value));
+                    }
+                }
+                OutputFormat::Plain => {
+                    for value in values {
+                        text.push_str(&format!("{}\n", value));
+                    }
+                }
+                OutputFormat::Nul => {
+                    for value in values {
+                        text.push_str(&format!("{}\0", value));
+                    }
+                }
+                OutputFormat::Json => {}
+            }
+        }
+        text
+    }
+}
+
+/// Valid shell variable name for the id: only letters, digits and `_`, not starting by a digit.
+fn shell_name(id: &str) -> String {
+    let name: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match name.chars().next() {
+        Some(c) if !c.is_ascii_digit() => name,
+        _ => format!("_{}", name),
+    }
+}
+
+/// Quotes the value with single quotes, so nothing inside it is expanded by the shell.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Response printed by a previous run, read back to use its values as defaults. Values of both
+/// versions of the response are accepted.
+#[derive(Deserialize)]
+pub struct SavedResponse {
+    #[serde(rename = "type")]
+    pub response_type: String,
+    #[serde(default)]
+    pub body: Vec<SavedValue>,
+}
+
+#[derive(Deserialize)]
+pub struct SavedValue {
+    pub id: String,
+    pub value: Value,
+}
+
+/// Acknowledgement of a command received from stdin while the dialog is open.
+#[derive(Serialize)]
+pub struct Ack {
+    #[serde(rename = "type")]
+    response_type: ResponseType,
+    cmd: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Ack {
+    pub fn result(cmd: &str, id: Option<String>, result: Result<(), String>) {
+        Ack {
+            response_type: ResponseType::Ack,
+            cmd: cmd.to_string(),
+            id,
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+        .print();
+    }
+
+    pub fn error(cmd: &str, id: Option<String>, error: String) {
+        Ack::result(cmd, id, Err(error));
+    }
+
     fn print(&self) {
         match serde_json::to_string(&self) {
             Ok(s) => {
                 println!("{}", s)
             }
             Err(e) => {
-                println!("Error serializing ResponseBodys {}", e)
+                eprintln!("Error serializing Ack {}", e)
             }
         }
-        std::process::exit(0);
     }
 }
 
+/// Change of the value of a field while the dialog is open.
 #[derive(Serialize)]
+pub struct Event<'a> {
+    #[serde(rename = "type")]
+    response_type: ResponseType,
+    #[serde(flatten)]
+    body: &'a ResponseBody,
+    old_value: Value,
+    timestamp: String,
+}
+
+impl<'a> Event<'a> {
+    pub fn change(body: &'a ResponseBody, old: &ResponseBody) {
+        let event = Event {
+            response_type: ResponseType::Change,
+            body,
+            old_value: old.output_value(),
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        };
+        match serde_json::to_string(&event) {
+            Ok(s) => write_output(&format!("{}\n", s)),
+            Err(e) => {
+                eprintln!("Error serializing Event {}", e)
+            }
+        }
+    }
+}
+
 pub struct ResponseBody {
     pub id: String,
+    /// Value as it is printed in the version 1 of the response
     pub value: String,
+    /// Value as it is printed in the version 2 of the response
+    pub typed_value: Value,
+}
+
+impl ResponseBody {
+    pub fn new(id: String, value: String, typed_value: Value) -> Self {
+        ResponseBody {
+            id,
+            value,
+            typed_value,
+        }
+    }
+
+    fn output_value(&self) -> Value {
+        match output().version {
+            ResponseVersion::V1 => Value::String(self.value.clone()),
+            ResponseVersion::V2 => self.typed_value.clone(),
+        }
+    }
+
+    /// Values of the field for the text formats. Lists (like the paths of the file dialog)
+    /// give a value per element.
+    fn text_values(&self) -> Vec<String> {
+        match &self.typed_value {
+            Value::Array(values) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                })
+                .collect(),
+            _ => vec![self.value.clone()],
+        }
+    }
+}
+
+impl Serialize for ResponseBody {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut body = serializer.serialize_struct("ResponseBody", 2)?;
+        body.serialize_field("id", &self.id)?;
+        body.serialize_field("value", &self.output_value())?;
+        body.end()
+    }
 }
 
 #[derive(Serialize)]
@@ -48,4 +510,50 @@ pub struct ResponseBody {
 enum ResponseType {
     Ok,
     Cancel,
+    Ack,
+    Change,
+    Error,
+    Timeout,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Value printed back by `sh` after evaluating the quoted string
+    fn sh_eval(quoted: &str) -> String {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf %s {}", quoted))
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn shell_quote_is_not_expanded() {
+        for value in [
+            "",
+            "plain",
+            "it's",
+            "''",
+            "two\nlines",
+            "$(echo injected)",
+            "`echo injected`",
+            "$HOME ${HOME} \\ \" * ?",
+        ] {
+            assert_eq!(sh_eval(&shell_quote(value)), value);
+        }
+    }
+
+    #[test]
+    fn shell_name_is_a_variable_name() {
+        assert_eq!(shell_name("name"), "name");
+        assert_eq!(shell_name("use-proxy"), "use_proxy");
+        assert_eq!(shell_name("a b.c$d"), "a_b_c_d");
+        assert_eq!(shell_name("été"), "_t_");
+        assert_eq!(shell_name("2fa"), "_2fa");
+        assert_eq!(shell_name("_x"), "_x");
+        assert_eq!(shell_name(""), "_");
+    }
 }
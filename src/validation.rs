@@ -0,0 +1,186 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use chrono::NaiveDate;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer};
+
+/// Rules checked for the value of a field before the dialog can be accepted.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Validation {
+    /// Regular expression the whole value must match
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    #[schemars(with = "Option<String>")]
+    pub pattern: Option<Regex>,
+    #[serde(default)]
+    pub min_length: Option<usize>,
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    /// Numeric range, for sliders and text fields that must contain a number
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Date range (%Y-%m-%d), for calendars
+    #[serde(default, deserialize_with = "deserialize_date")]
+    #[schemars(with = "Option<String>")]
+    pub min_date: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    #[schemars(with = "Option<String>")]
+    pub max_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub preset: Option<Preset>,
+    /// Message shown instead of the default one when any rule fails
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    Email,
+    Url,
+    Ip,
+    Ipv4,
+    Ipv6,
+    Number,
+    Integer,
+}
+
+/// Value of a field to be checked against the rules.
+pub enum Checked<'a> {
+    Text(&'a str),
+    Number(f64),
+    Date(NaiveDate),
+}
+
+impl Validation {
+    pub fn check(&self, value: Checked) -> Result<(), String> {
+        match self.first_error(value) {
+            Some(error) => Err(self.message.clone().unwrap_or(error)),
+            None => Ok(()),
+        }
+    }
+
+    fn first_error(&self, value: Checked) -> Option<String> {
+        match value {
+            Checked::Text(text) => {
+                let length = text.chars().count();
+                if let Some(min_length) = self.min_length {
+                    if length < min_length {
+                        return Some(format!("Must be at least {} characters", min_length));
+                    }
+                }
+                if let Some(max_length) = self.max_length {
+                    if length > max_length {
+                        return Some(format!("Must be at most {} characters", max_length));
+                    }
+                }
+                if let Some(pattern) = &self.pattern {
+                    if !pattern.is_match(text) {
+                        return Some("Invalid format".to_string());
+                    }
+                }
+                if let Some(preset) = &self.preset {
+                    if let Some(error) = preset.check(text) {
+                        return Some(error);
+                    }
+                }
+                if self.min.is_some() || self.max.is_some() {
+                    return match text.trim().parse::<f64>() {
+                        Ok(number) => self.check_range(number),
+                        Err(_) => Some("Must be a number".to_string()),
+                    };
+                }
+                None
+            }
+            Checked::Number(number) => self.check_range(number),
+            Checked::Date(date) => {
+                if let Some(min_date) = self.min_date {
+                    if date < min_date {
+                        return Some(format!("Must be {} or later", min_date));
+                    }
+                }
+                if let Some(max_date) = self.max_date {
+                    if date > max_date {
+                        return Some(format!("Must be {} or earlier", max_date));
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn check_range(&self, number: f64) -> Option<String> {
+        if let Some(min) = self.min {
+            if number < min {
+                return Some(format!("Must be {} or greater", min));
+            }
+        }
+        if let Some(max) = self.max {
+            if number > max {
+                return Some(format!("Must be {} or less", max));
+            }
+        }
+        None
+    }
+}
+
+impl Preset {
+    fn check(&self, text: &str) -> Option<String> {
+        let valid = match self {
+            Preset::Email => Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$")
+                .unwrap()
+                .is_match(text),
+            Preset::Url => Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*://[^\s/?#]+\S*$")
+                .unwrap()
+                .is_match(text),
+            Preset::Ip => text.parse::<IpAddr>().is_ok(),
+            Preset::Ipv4 => text.parse::<Ipv4Addr>().is_ok(),
+            Preset::Ipv6 => text.parse::<Ipv6Addr>().is_ok(),
+            Preset::Number => text.trim().parse::<f64>().is_ok(),
+            Preset::Integer => text.trim().parse::<i64>().is_ok(),
+        };
+        if valid {
+            return None;
+        }
+        Some(
+            match self {
+                Preset::Email => "Must be a valid email address",
+                Preset::Url => "Must be a valid URL",
+                Preset::Ip => "Must be a valid IP address",
+                Preset::Ipv4 => "Must be a valid IPv4 address",
+                Preset::Ipv6 => "Must be a valid IPv6 address",
+                Preset::Number => "Must be a number",
+                Preset::Integer => "Must be an integer",
+            }
+            .to_string(),
+        )
+    }
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        // The pattern has to match the whole value, not only a part of it
+        Some(pattern) => Regex::new(&format!("^(?:{})$", pattern))
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,170 @@
+//! `notification --wait` against a stand-in notification server on a private D-Bus session bus.
+//! The tests are skipped when `dbus-daemon` isn't installed.
+#![cfg(all(unix, not(target_os = "macos")))]
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::OwnedValue;
+
+const ID: u32 = 7;
+
+/// What the server does with the notification once it is shown
+#[derive(Clone, Copy)]
+enum Reply {
+    Action(&'static str),
+    Close(u32),
+}
+
+struct Server {
+    reply: Reply,
+}
+
+#[zbus::interface(name = "org.freedesktop.Notifications")]
+impl Server {
+    #[allow(clippy::too_many_arguments)]
+    async fn notify(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        _app_name: String,
+        _replaces_id: u32,
+        _app_icon: String,
+        _summary: String,
+        _body: String,
+        _actions: Vec<String>,
+        _hints: HashMap<String, OwnedValue>,
+        _expire_timeout: i32,
+    ) -> u32 {
+        let emitter = emitter.to_owned();
+        let reply = self.reply;
+        // Sent when clialogs is already listening to the signals
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(500));
+            zbus::block_on(async {
+                match reply {
+                    Reply::Action(action) => Server::action_invoked(&emitter, ID, action).await,
+                    Reply::Close(reason) => Server::notification_closed(&emitter, ID, reason).await,
+                }
+            })
+            .unwrap();
+        });
+        ID
+    }
+
+    fn close_notification(&self, _id: u32) {}
+
+    fn get_capabilities(&self) -> Vec<String> {
+        vec!["actions".to_string(), "body".to_string()]
+    }
+
+    fn get_server_information(&self) -> (String, String, String, String) {
+        (
+            "stand-in".to_string(),
+            "clialogs".to_string(),
+            "1.0".to_string(),
+            "1.2".to_string(),
+        )
+    }
+
+    #[zbus(signal)]
+    async fn action_invoked(emitter: &SignalEmitter<'_>, id: u32, action: &str)
+        -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn notification_closed(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        reason: u32,
+    ) -> zbus::Result<()>;
+}
+
+/// Private session bus, stopped when dropped
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn start() -> Option<Bus> {
+        let daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut bus = Bus {
+            daemon,
+            address: String::new(),
+        };
+        let stdout = bus.daemon.stdout.take()?;
+        BufReader::new(stdout).read_line(&mut bus.address).ok()?;
+        bus.address = bus.address.trim().to_string();
+        Some(bus)
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// Shows a notification with actions and waits for the reply of the server. Returns the
+/// response and the exit code.
+fn notify_and_wait(reply: Reply) -> Option<(Value, Option<i32>)> {
+    let Some(bus) = Bus::start() else {
+        eprintln!("dbus-daemon is not available, skipping");
+        return None;
+    };
+    let _server = zbus::blocking::connection::Builder::address(bus.address.as_str())
+        .unwrap()
+        .name("org.freedesktop.Notifications")
+        .unwrap()
+        .serve_at("/org/freedesktop/Notifications", Server { reply })
+        .unwrap()
+        .build()
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_clialogs"))
+        .args(["notification", "--title", "Backup", "--text", "Start now?"])
+        .args(["--action", "yes:Yes", "--action", "no:No", "--wait"])
+        .env("DBUS_SESSION_BUS_ADDRESS", &bus.address)
+        .output()
+        .unwrap();
+    let response = serde_json::from_slice(&output.stdout).unwrap();
+    Some((response, output.status.code()))
+}
+
+#[test]
+fn wait_prints_the_clicked_action() {
+    let Some((response, code)) = notify_and_wait(Reply::Action("yes")) else {
+        return;
+    };
+    assert_eq!(code, Some(0));
+    assert_eq!(
+        response,
+        json!({
+            "type": "ok",
+            "body": [
+                { "id": "id", "value": ID.to_string() },
+                { "id": "action", "value": "yes" },
+            ],
+        })
+    );
+}
+
+#[test]
+fn wait_prints_the_close_reason() {
+    let Some((response, code)) = notify_and_wait(Reply::Close(2)) else {
+        return;
+    };
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        response,
+        json!({ "type": "cancel", "body": [], "reason": "dismissed" })
+    );
+}